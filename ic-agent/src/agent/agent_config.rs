@@ -1,14 +1,23 @@
 use crate::agent::{NonceFactory, ReplicaV2Transport};
 use crate::identity::anonymous::AnonymousIdentity;
-use crate::identity::Identity;
+use crate::identity::AsyncIdentity;
 use std::sync::Arc;
 
 /// A configuration for an agent.
 pub struct AgentConfig {
     pub nonce_factory: NonceFactory,
-    pub identity: Arc<dyn Identity + Send + Sync>,
+    pub identity: Arc<dyn AsyncIdentity>,
     pub ingress_expiry_duration: Option<std::time::Duration>,
     pub transport: Option<Arc<dyn ReplicaV2Transport + Send + Sync>>,
+    /// The root key used to verify certificates, either DER-encoded or as a raw BLS
+    /// public key. If [None], the Internet Computer mainnet root key is used.
+    pub root_key: Option<Vec<u8>>,
+    /// Whether [`crate::Agent::fetch_root_key`] may trust the status endpoint of a replica
+    /// that is not running locally.
+    pub allow_fetch_root_key: bool,
+    /// The maximum age of a certificate, based on its certified time, for it to be
+    /// accepted. If [None], defaults to 5 minutes.
+    pub max_certificate_age: Option<std::time::Duration>,
 }
 
 impl Default for AgentConfig {
@@ -18,6 +27,9 @@ impl Default for AgentConfig {
             identity: Arc::new(AnonymousIdentity {}),
             ingress_expiry_duration: None,
             transport: None,
+            root_key: None,
+            allow_fetch_root_key: false,
+            max_certificate_age: None,
         }
     }
 }
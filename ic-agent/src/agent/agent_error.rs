@@ -1,5 +1,7 @@
+use crate::agent::response::RejectCode;
 use crate::agent::status::Status;
 use crate::hash_tree::Label;
+use crate::identity::IdentityError;
 use crate::RequestIdError;
 use leb128::read;
 use std::fmt::{Debug, Display, Formatter};
@@ -18,7 +20,7 @@ pub enum AgentError {
     WaiterRestartError(),
 
     #[error("Identity had a signing error: {0}")]
-    SigningError(String),
+    SigningError(#[from] IdentityError),
 
     #[error("Invalid CBOR data, could not deserialize: {0}")]
     InvalidCborData(#[from] serde_cbor::Error),
@@ -37,7 +39,7 @@ pub enum AgentError {
 
     #[error(r#"The Replica returned an error: code {reject_code}, message: "{reject_message}""#)]
     ReplicaError {
-        reject_code: u64,
+        reject_code: RejectCode,
         reject_message: String,
     },
 
@@ -56,6 +58,12 @@ pub enum AgentError {
     #[error("Call was marked as done but we never saw the reply. Request ID: {0}")]
     RequestStatusDoneNoReply(String),
 
+    #[error("The request {request_id} expired (at {ingress_expiry}) before the replica accepted it. It did not execute, and can be submitted again.")]
+    RequestExpired {
+        request_id: String,
+        ingress_expiry: u64,
+    },
+
     #[error("A tool returned a string message error: {0}")]
     MessageError(String),
 
@@ -83,6 +91,23 @@ pub enum AgentError {
     #[error("Certificate verification failed.")]
     CertificateVerificationFailed(),
 
+    #[error("A delegation certificate cannot itself contain a delegation.")]
+    CertificateHasTooManyDelegations(),
+
+    #[error(
+        "The subnet {subnet_id:?} is not authorized to certify answers for canister {canister_id}."
+    )]
+    CertificateNotAuthorized {
+        subnet_id: Vec<u8>,
+        canister_id: crate::export::Principal,
+    },
+
+    #[error("Certificate is not fresh: it certifies time {certificate_time}ns, but the local time is {local_time}ns.")]
+    CertificateNotFresh {
+        certificate_time: u64,
+        local_time: u64,
+    },
+
     #[error(
         r#"BLS DER-encoded public key must be ${expected} bytes long, but is {actual} bytes long."#
     )]
@@ -97,6 +122,9 @@ pub enum AgentError {
     #[error("Could not read the root key")]
     CouldNotReadRootKey(),
 
+    #[error("Refusing to fetch the root key from a replica that is not local. Pin the root key with AgentBuilder::with_root_key(), or allow fetching it with AgentBuilder::with_allow_fetch_root_key().")]
+    RootKeyFetchNotAllowed(),
+
     #[error("Failed to initialize the BLS library")]
     BlsInitializationFailure(),
 
@@ -106,10 +134,99 @@ pub enum AgentError {
     #[error("Missing replica transport in the Agent Builder.")]
     MissingReplicaTransport(),
 
+    #[error("Invalid transport configuration: {0}")]
+    InvalidTransportConfig(String),
+
     #[error("An error happened during communication with the replica: {0}")]
     TransportError(Box<dyn std::error::Error + Send + Sync>),
 }
 
+/// A broad classification of [AgentError]s, so callers can decide how to handle an error
+/// without matching on every variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AgentErrorKind {
+    /// A transient failure; the same request might succeed if tried again later.
+    Retryable,
+    /// The request itself is wrong (bad arguments, invalid destination, rejected or failed
+    /// canister call, misconfiguration); retrying it will fail the same way.
+    User,
+    /// The replica answered in a way that violates the protocol, or could not be verified.
+    Protocol,
+    /// The agent could not communicate with the replica.
+    Transport,
+    /// The agent stopped waiting for the outcome of a call, which may or may not have been
+    /// executed. Submitting a new call might execute it twice.
+    Indeterminate,
+}
+
+impl AgentError {
+    /// Classify the error.
+    pub fn kind(&self) -> AgentErrorKind {
+        match self {
+            AgentError::ReplicaError { reject_code, .. } => match reject_code {
+                RejectCode::SysTransient => AgentErrorKind::Retryable,
+                RejectCode::SysFatal => AgentErrorKind::Protocol,
+                RejectCode::DestinationInvalid
+                | RejectCode::CanisterReject
+                | RejectCode::CanisterError => AgentErrorKind::User,
+                RejectCode::Unknown(_) => AgentErrorKind::Protocol,
+            },
+            AgentError::HttpError(payload) => match payload.status {
+                429 | 502 | 503 | 504 => AgentErrorKind::Retryable,
+                400..=499 => AgentErrorKind::User,
+                500..=599 => AgentErrorKind::Transport,
+                _ => AgentErrorKind::Protocol,
+            },
+            AgentError::TimeoutWaitingForResponse() => AgentErrorKind::Indeterminate,
+            AgentError::RequestExpired { .. } => AgentErrorKind::Retryable,
+            AgentError::TransportError(_) => AgentErrorKind::Transport,
+            AgentError::SigningError(IdentityError::Unavailable(_)) => AgentErrorKind::Retryable,
+            AgentError::InvalidCborData(_)
+            | AgentError::InvalidReplicaStatus
+            | AgentError::RequestStatusDoneNoReply(_)
+            | AgentError::Leb128ReadError(_)
+            | AgentError::Utf8ReadError(_)
+            | AgentError::LookupPathAbsent(_)
+            | AgentError::LookupPathUnknown(_)
+            | AgentError::LookupPathError(_)
+            | AgentError::InvalidRequestStatus(..)
+            | AgentError::CertificateVerificationFailed()
+            | AgentError::CertificateHasTooManyDelegations()
+            | AgentError::CertificateNotAuthorized { .. }
+            | AgentError::CertificateNotFresh { .. }
+            | AgentError::NoRootKeyInStatus(_)
+            | AgentError::BlsInitializationFailure() => AgentErrorKind::Protocol,
+            AgentError::InvalidReplicaUrl(_)
+            | AgentError::WaiterRestartError()
+            | AgentError::SigningError(_)
+            | AgentError::CannotCalculateRequestId(_)
+            | AgentError::CandidError(_)
+            | AgentError::UrlParseError(_)
+            | AgentError::PrincipalError(_)
+            | AgentError::CannotUseAuthenticationOnNonSecureUrl()
+            | AgentError::AuthenticationError(_)
+            | AgentError::MessageError(_)
+            | AgentError::CustomError(_)
+            | AgentError::DerKeyLengthMismatch { .. }
+            | AgentError::DerPrefixMismatch { .. }
+            | AgentError::CouldNotReadRootKey()
+            | AgentError::RootKeyFetchNotAllowed()
+            | AgentError::WalletCallFailed(_)
+            | AgentError::MissingReplicaTransport()
+            | AgentError::InvalidTransportConfig(_) => AgentErrorKind::User,
+        }
+    }
+
+    /// Whether trying the same request again might succeed. This is the case for
+    /// [AgentErrorKind::Retryable] and [AgentErrorKind::Transport] errors.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self.kind(),
+            AgentErrorKind::Retryable | AgentErrorKind::Transport
+        )
+    }
+}
+
 impl PartialEq for AgentError {
     fn eq(&self, other: &Self) -> bool {
         // Verify the debug string is the same. Some of the subtypes of this error
@@ -157,9 +274,69 @@ impl Display for HttpErrorPayload {
 
 #[cfg(test)]
 mod tests {
-    use super::HttpErrorPayload;
+    use super::{AgentErrorKind, HttpErrorPayload};
+    use crate::agent::RejectCode;
+    use crate::identity::IdentityError;
     use crate::AgentError;
 
+    #[test]
+    fn error_kind() {
+        let replica_error = |reject_code| AgentError::ReplicaError {
+            reject_code,
+            reject_message: String::new(),
+        };
+        let http_error = |status| {
+            AgentError::HttpError(HttpErrorPayload {
+                status,
+                content_type: None,
+                content: vec![],
+            })
+        };
+
+        assert_eq!(
+            replica_error(RejectCode::SysTransient).kind(),
+            AgentErrorKind::Retryable
+        );
+        assert_eq!(
+            replica_error(RejectCode::SysFatal).kind(),
+            AgentErrorKind::Protocol
+        );
+        assert_eq!(
+            replica_error(RejectCode::CanisterReject).kind(),
+            AgentErrorKind::User
+        );
+        assert_eq!(
+            replica_error(RejectCode::Unknown(42)).kind(),
+            AgentErrorKind::Protocol
+        );
+        assert_eq!(http_error(503).kind(), AgentErrorKind::Retryable);
+        assert_eq!(http_error(404).kind(), AgentErrorKind::User);
+        assert_eq!(http_error(500).kind(), AgentErrorKind::Transport);
+        assert_eq!(
+            AgentError::CertificateVerificationFailed().kind(),
+            AgentErrorKind::Protocol
+        );
+
+        assert_eq!(
+            AgentError::SigningError(IdentityError::Unavailable(String::new())).kind(),
+            AgentErrorKind::Retryable
+        );
+        assert_eq!(
+            AgentError::SigningError(IdentityError::Refused(String::new())).kind(),
+            AgentErrorKind::User
+        );
+
+        assert_eq!(
+            AgentError::TimeoutWaitingForResponse().kind(),
+            AgentErrorKind::Indeterminate
+        );
+        assert!(!AgentError::TimeoutWaitingForResponse().is_retryable());
+
+        assert!(replica_error(RejectCode::SysTransient).is_retryable());
+        assert!(http_error(500).is_retryable());
+        assert!(!replica_error(RejectCode::CanisterError).is_retryable());
+    }
+
     #[test]
     fn content_type_none_valid_utf8() {
         let payload = HttpErrorPayload {
@@ -1,12 +1,19 @@
 // Disable these tests without the reqwest feature.
 #![cfg(feature = "reqwest")]
 
-use crate::agent::replica_api::{CallReply, QueryResponse};
-use crate::agent::Status;
+use crate::agent::agent_error::HttpErrorPayload;
+use crate::agent::http_transport::{ReqwestHttpReplicaV2Transport, RetryPolicy};
+use crate::agent::replica_api::{CallReply, Certificate, QueryResponse};
+use crate::agent::test_transport::{connection_error, TestTransport};
+use crate::agent::{verify, Backoff, RejectCode, SignedUpdate, Status};
 use crate::export::Principal;
-use crate::{Agent, AgentError};
+use crate::{Agent, AgentError, AsyncIdentity, IdentityError, RequestId, Signature};
 use mockito::mock;
 use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
 
 #[test]
 fn query() -> Result<(), AgentError> {
@@ -26,6 +33,7 @@ fn query() -> Result<(), AgentError> {
     let result = runtime.block_on(async {
         agent
             .query_raw(
+                &*agent.identity,
                 &Principal::management_canister(),
                 Principal::management_canister(),
                 "main",
@@ -53,6 +61,7 @@ fn query_error() -> Result<(), AgentError> {
     let result = runtime.block_on(async {
         agent
             .query_raw(
+                &*agent.identity,
                 &Principal::management_canister(),
                 Principal::management_canister(),
                 "greet",
@@ -72,7 +81,7 @@ fn query_error() -> Result<(), AgentError> {
 #[test]
 fn query_rejected() -> Result<(), AgentError> {
     let response: QueryResponse = QueryResponse::Rejected {
-        reject_code: 1234,
+        reject_code: RejectCode::CanisterError,
         reject_message: "Rejected Message".to_string(),
     };
 
@@ -88,6 +97,7 @@ fn query_rejected() -> Result<(), AgentError> {
     let result = runtime.block_on(async {
         agent
             .query_raw(
+                &*agent.identity,
                 &Principal::management_canister(),
                 Principal::management_canister(),
                 "greet",
@@ -104,7 +114,7 @@ fn query_rejected() -> Result<(), AgentError> {
             reject_code: code,
             reject_message: msg,
         }) => {
-            assert_eq!(code, 1234);
+            assert_eq!(code, RejectCode::CanisterError);
             assert_eq!(msg, "Rejected Message");
         }
         result => unreachable!("{:?}", result),
@@ -203,3 +213,366 @@ fn status_error() -> Result<(), AgentError> {
 
     Ok(())
 }
+
+#[test]
+fn default_root_key_is_mainnet() -> Result<(), AgentError> {
+    let agent = Agent::builder().with_url("https://ic0.app").build()?;
+
+    assert_eq!(agent.read_root_key()?, super::IC_ROOT_KEY.to_vec());
+
+    Ok(())
+}
+
+#[test]
+fn with_root_key_accepts_raw_key() -> Result<(), AgentError> {
+    let raw_key = super::IC_ROOT_KEY[37..].to_vec();
+    let agent = Agent::builder()
+        .with_url("https://ic0.app")
+        .with_root_key(raw_key)
+        .build()?;
+
+    assert_eq!(agent.read_root_key()?, super::IC_ROOT_KEY.to_vec());
+
+    Ok(())
+}
+
+#[test]
+fn with_root_key_rejects_invalid_key() {
+    let result = Agent::builder()
+        .with_url("https://ic0.app")
+        .with_root_key(vec![0; 42])
+        .build();
+
+    assert!(matches!(
+        result,
+        Err(AgentError::DerKeyLengthMismatch { actual: 42, .. })
+    ));
+}
+
+#[test]
+fn with_url_rejects_invalid_url() {
+    let result = Agent::builder().with_url("not a url").build();
+    assert!(matches!(result, Err(AgentError::InvalidReplicaUrl(_))));
+
+    #[cfg(all(unix, feature = "unix-socket"))]
+    {
+        let result = Agent::builder()
+            .with_url("unix://host/replica.sock")
+            .build();
+        assert!(matches!(result, Err(AgentError::InvalidReplicaUrl(_))));
+    }
+}
+
+#[test]
+fn fetch_root_key_refused_for_remote_replica() -> Result<(), AgentError> {
+    let agent = Agent::builder().with_url("https://ic0.app").build()?;
+    let runtime = tokio::runtime::Runtime::new().expect("Unable to create a runtime");
+    let result = runtime.block_on(agent.fetch_root_key());
+
+    assert_eq!(result, Err(AgentError::RootKeyFetchNotAllowed()));
+    assert_eq!(agent.read_root_key()?, super::IC_ROOT_KEY.to_vec());
+
+    Ok(())
+}
+
+#[test]
+fn fetch_root_key_local_replica() -> Result<(), AgentError> {
+    let root_key = vec![0x42; 96];
+    let mut map = BTreeMap::new();
+    map.insert(
+        serde_cbor::Value::Text("ic_api_version".to_owned()),
+        serde_cbor::Value::Text("1.2.3".to_owned()),
+    );
+    map.insert(
+        serde_cbor::Value::Text("root_key".to_owned()),
+        serde_cbor::Value::Bytes(root_key.clone()),
+    );
+    let response = serde_cbor::Value::Map(map);
+    let read_mock = mock("GET", "/api/v2/status")
+        .with_status(200)
+        .with_body(serde_cbor::to_vec(&response)?)
+        .create();
+
+    let agent = Agent::builder().with_url(mockito::server_url()).build()?;
+    let runtime = tokio::runtime::Runtime::new().expect("Unable to create a runtime");
+    runtime.block_on(agent.fetch_root_key())?;
+
+    read_mock.assert();
+
+    let mut expected = super::IC_ROOT_KEY[..37].to_vec();
+    expected.extend_from_slice(&root_key);
+    assert_eq!(agent.read_root_key()?, expected);
+
+    Ok(())
+}
+
+/// Creates an (unsigned) certificate whose tree only contains the given time.
+fn certificate_with_time(time: u64) -> Result<Certificate, AgentError> {
+    let mut encoded_time = vec![];
+    leb128::write::unsigned(&mut encoded_time, time).unwrap();
+
+    let tree = serde_cbor::Value::Array(vec![
+        serde_cbor::Value::Integer(2),
+        serde_cbor::Value::Bytes(b"time".to_vec()),
+        serde_cbor::Value::Array(vec![
+            serde_cbor::Value::Integer(3),
+            serde_cbor::Value::Bytes(encoded_time),
+        ]),
+    ]);
+    let mut map = BTreeMap::new();
+    map.insert(serde_cbor::Value::Text("tree".to_owned()), tree);
+    map.insert(
+        serde_cbor::Value::Text("signature".to_owned()),
+        serde_cbor::Value::Bytes(vec![]),
+    );
+
+    let bytes = serde_cbor::to_vec(&serde_cbor::Value::Map(map))?;
+    Ok(serde_cbor::from_slice(&bytes)?)
+}
+
+fn now() -> std::time::Duration {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+}
+
+#[test]
+fn certificate_time_fresh() -> Result<(), AgentError> {
+    let agent = Agent::builder().with_url("https://ic0.app").build()?;
+    let cert = certificate_with_time(now().as_nanos() as u64)?;
+
+    agent.check_certificate_time(&cert)
+}
+
+#[test]
+fn certificate_time_stale() -> Result<(), AgentError> {
+    let agent = Agent::builder()
+        .with_url("https://ic0.app")
+        .with_max_certificate_age(Some(std::time::Duration::from_secs(60)))
+        .build()?;
+    let time = (now() - std::time::Duration::from_secs(120)).as_nanos() as u64;
+    let cert = certificate_with_time(time)?;
+
+    assert!(matches!(
+        agent.check_certificate_time(&cert),
+        Err(AgentError::CertificateNotFresh { certificate_time, .. }) if certificate_time == time
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn certificate_time_in_the_future() -> Result<(), AgentError> {
+    let agent = Agent::builder().with_url("https://ic0.app").build()?;
+    let time = (now() + std::time::Duration::from_secs(600)).as_nanos() as u64;
+    let cert = certificate_with_time(time)?;
+
+    assert!(matches!(
+        agent.check_certificate_time(&cert),
+        Err(AgentError::CertificateNotFresh { certificate_time, .. }) if certificate_time == time
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn canister_ranges() -> Result<(), AgentError> {
+    use std::convert::TryFrom;
+    let canister_id = |n: u8| Principal::try_from(vec![0, 0, 0, 0, 0, 0, 0, n, 1, 1]);
+    let ranges = vec![
+        (
+            canister_id(2)?.as_slice().to_vec(),
+            canister_id(4)?.as_slice().to_vec(),
+        ),
+        (
+            canister_id(8)?.as_slice().to_vec(),
+            canister_id(8)?.as_slice().to_vec(),
+        ),
+    ];
+
+    assert!(!super::principal_is_within_ranges(
+        &canister_id(1)?,
+        &ranges
+    ));
+    assert!(super::principal_is_within_ranges(&canister_id(2)?, &ranges));
+    assert!(super::principal_is_within_ranges(&canister_id(3)?, &ranges));
+    assert!(super::principal_is_within_ranges(&canister_id(4)?, &ranges));
+    assert!(!super::principal_is_within_ranges(
+        &canister_id(5)?,
+        &ranges
+    ));
+    assert!(super::principal_is_within_ranges(&canister_id(8)?, &ranges));
+    assert!(!super::principal_is_within_ranges(
+        &Principal::management_canister(),
+        &ranges
+    ));
+
+    Ok(())
+}
+
+/// A signer that cannot be reached, to check the agent awaits asynchronous identities.
+struct UnavailableSigner;
+
+impl AsyncIdentity for UnavailableSigner {
+    fn sender(&self) -> Result<Principal, IdentityError> {
+        Ok(Principal::anonymous())
+    }
+
+    fn public_key(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn sign<'a>(
+        &'a self,
+        _blob: &'a [u8],
+    ) -> Pin<Box<dyn Future<Output = Result<Signature, IdentityError>> + Send + 'a>> {
+        Box::pin(async { Err(IdentityError::Unavailable("Not connected.".to_string())) })
+    }
+}
+
+#[test]
+fn query_async_identity_error() -> Result<(), AgentError> {
+    let agent = Agent::builder()
+        .with_url(&mockito::server_url())
+        .with_identity(UnavailableSigner)
+        .build()?;
+    let runtime = tokio::runtime::Runtime::new().expect("Unable to create a runtime");
+    let result = runtime.block_on(async {
+        agent
+            .query_raw(
+                &*agent.identity,
+                &Principal::management_canister(),
+                Principal::management_canister(),
+                "main",
+                &[],
+                None,
+            )
+            .await
+    });
+
+    match result {
+        Err(err @ AgentError::SigningError(IdentityError::Unavailable(_))) => {
+            assert!(err.is_retryable())
+        }
+        result => panic!("Unexpected result: {:?}", result),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn query_identity_override() -> Result<(), AgentError> {
+    let agent = Agent::builder().with_url(&mockito::server_url()).build()?;
+    let runtime = tokio::runtime::Runtime::new().expect("Unable to create a runtime");
+    let result = runtime.block_on(async {
+        agent
+            .query(&Principal::management_canister(), "main")
+            .with_identity(Arc::new(UnavailableSigner))
+            .call()
+            .await
+    });
+
+    assert!(matches!(
+        result,
+        Err(AgentError::SigningError(IdentityError::Unavailable(_)))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn sign_and_submit_update() -> Result<(), AgentError> {
+    let canister_id = Principal::from_text("rwlgt-iiaaa-aaaaa-aaaaa-cai")?;
+    let call_mock = mock("POST", "/api/v2/canister/rwlgt-iiaaa-aaaaa-aaaaa-cai/call")
+        .with_status(202)
+        .create();
+
+    let agent = Agent::builder().with_url(&mockito::server_url()).build()?;
+    let runtime = tokio::runtime::Runtime::new().expect("Unable to create a runtime");
+    let signed = runtime.block_on(async {
+        agent
+            .update(&canister_id, "greet")
+            .with_arg(&[1, 2, 3])
+            .sign()
+            .await
+    })?;
+    assert_eq!(signed.canister_id, canister_id);
+    assert_eq!(signed.effective_canister_id, canister_id);
+    assert_eq!(signed.arg, vec![1, 2, 3]);
+
+    // The signed update can be stored, and submitted later.
+    let signed: SignedUpdate = serde_cbor::from_slice(&serde_cbor::to_vec(&signed)?)?;
+    let envelope = verify::decode_envelope(&signed.signed_update).unwrap();
+    assert_eq!(
+        verify::verify_envelope(&envelope).unwrap(),
+        signed.request_id
+    );
+
+    let request_id = runtime.block_on(async { agent.submit_update(&signed).await })?;
+
+    call_mock.assert();
+    assert_eq!(request_id, signed.request_id);
+
+    Ok(())
+}
+
+#[test]
+fn call_and_wait_resubmits_same_envelope() -> Result<(), AgentError> {
+    let transport = TestTransport::new(|| Err(connection_error()));
+    let calls = transport.requests();
+    let agent = Agent::builder().with_transport(transport).build()?;
+    let runtime = tokio::runtime::Runtime::new().expect("Unable to create a runtime");
+    let result = runtime.block_on(async {
+        agent
+            .update(&Principal::management_canister(), "greet")
+            .call_and_wait_resubmitting(
+                Backoff::throttle(Duration::from_millis(5))
+                    .with_timeout(Duration::from_millis(100)),
+            )
+            .await
+    });
+
+    assert!(matches!(
+        result,
+        Err(AgentError::TimeoutWaitingForResponse())
+    ));
+    let calls = calls.lock().unwrap();
+    assert!(calls.len() > 1);
+    assert!(calls.iter().all(|call| *call == calls[0]));
+
+    Ok(())
+}
+
+#[test]
+fn query_retried_on_unavailable() -> Result<(), AgentError> {
+    let canister_id = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai")?;
+    let query_mock = mock("POST", "/api/v2/canister/ryjl3-tyaaa-aaaaa-aaaba-cai/query")
+        .with_status(503)
+        .expect(3)
+        .create();
+    let call_mock = mock("POST", "/api/v2/canister/ryjl3-tyaaa-aaaaa-aaaba-cai/call")
+        .with_status(503)
+        .expect(1)
+        .create();
+
+    let transport = ReqwestHttpReplicaV2Transport::create(mockito::server_url())?
+        .with_retry_policy(RetryPolicy::default().with_max_retries(2).with_backoff(
+            Duration::from_millis(1),
+            1.0,
+            Duration::from_millis(1),
+        ));
+    let agent = Agent::builder().with_transport(transport).build()?;
+    let runtime = tokio::runtime::Runtime::new().expect("Unable to create a runtime");
+    let query_result = runtime.block_on(agent.query(&canister_id, "greet").call());
+    let call_result = runtime.block_on(agent.update(&canister_id, "greet").call());
+
+    query_mock.assert();
+    call_mock.assert();
+    assert!(matches!(
+        query_result,
+        Err(AgentError::HttpError(HttpErrorPayload { status: 503, .. }))
+    ));
+    assert!(call_result.is_err());
+
+    Ok(())
+}
@@ -1,15 +1,18 @@
 use crate::agent::{AgentConfig, ReplicaV2Transport};
-use crate::{Agent, AgentError, Identity, NonceFactory};
+use crate::{Agent, AgentError, AsyncIdentity, Identity, NonceFactory, SyncIdentity};
 use std::sync::Arc;
 
 pub struct AgentBuilder {
     config: AgentConfig,
+    /// An error of a builder method, returned by [build](AgentBuilder::build).
+    error: Option<AgentError>,
 }
 
 impl Default for AgentBuilder {
     fn default() -> Self {
         Self {
             config: Default::default(),
+            error: None,
         }
     }
 }
@@ -17,16 +20,53 @@ impl Default for AgentBuilder {
 impl AgentBuilder {
     /// Create an instance of [Agent] with the information from this builder.
     pub fn build(self) -> Result<Agent, AgentError> {
-        Agent::new(self.config)
+        match self.error {
+            Some(error) => Err(error),
+            None => Agent::new(self.config),
+        }
     }
 
-    /// Set the URL of the [Agent].
-    #[cfg(feature = "reqwest")]
+    /// Set the URL of the [Agent]. An invalid URL makes [build](AgentBuilder::build) fail.
+    ///
+    /// A URL of the form `unix:///path/to/socket` talks to a replica listening on a Unix
+    /// domain socket.
+    #[cfg(any(feature = "reqwest", all(unix, feature = "unix-socket")))]
     #[deprecated(since = "0.3.0", note = "Prefer using with_transport().")]
     pub fn with_url<S: Into<String>>(self, url: S) -> Self {
-        use crate::agent::http_transport::ReqwestHttpReplicaV2Transport;
+        let url = url.into();
+
+        #[cfg(all(unix, feature = "unix-socket"))]
+        {
+            use crate::agent::unix_socket_transport::{
+                UnixSocketReplicaV2Transport, UNIX_SOCKET_SCHEME,
+            };
+
+            if url.starts_with(&format!("{}:", UNIX_SOCKET_SCHEME)) {
+                return self.with_transport_result(UnixSocketReplicaV2Transport::from_url(&url));
+            }
+        }
+
+        #[cfg(feature = "reqwest")]
+        let transport = crate::agent::http_transport::ReqwestHttpReplicaV2Transport::create(url);
+        #[cfg(not(feature = "reqwest"))]
+        let transport =
+            crate::agent::unix_socket_transport::UnixSocketReplicaV2Transport::from_url(&url);
+
+        self.with_transport_result(transport)
+    }
 
-        self.with_transport(ReqwestHttpReplicaV2Transport::create(url).unwrap())
+    #[cfg(any(feature = "reqwest", all(unix, feature = "unix-socket")))]
+    fn with_transport_result<F: 'static + ReplicaV2Transport + Send + Sync>(
+        self,
+        transport: Result<F, AgentError>,
+    ) -> Self {
+        match transport {
+            Ok(transport) => self.with_transport(transport),
+            Err(error) => Self {
+                error: Some(error),
+                ..self
+            },
+        }
     }
 
     /// Set a Replica transport to talk to serve as the replica interface.
@@ -39,40 +79,60 @@ impl AgentBuilder {
                 transport: Some(Arc::new(transport)),
                 ..self.config
             },
+            ..self
+        }
+    }
+
+    /// Same as [with_transport], but provides a boxed implementation instead
+    /// of a direct type.
+    pub fn with_boxed_transport(
+        self,
+        transport: Box<dyn ReplicaV2Transport + Send + Sync>,
+    ) -> Self {
+        Self {
+            config: AgentConfig {
+                transport: Some(Arc::from(transport)),
+                ..self.config
+            },
+            ..self
         }
     }
 
     /// Add a NonceFactory to this Agent. By default, no nonce is produced.
     pub fn with_nonce_factory(self, nonce_factory: NonceFactory) -> Self {
-        AgentBuilder {
+        Self {
             config: AgentConfig {
                 nonce_factory,
                 ..self.config
             },
+            ..self
         }
     }
 
-    /// Add an identity provider for signing messages. This is required.
+    /// Add an identity provider for signing messages. This is required. Other
+    /// [Identity] implementations can be used by wrapping them in a [SyncIdentity].
     pub fn with_identity<I>(self, identity: I) -> Self
     where
-        I: 'static + Identity + Send + Sync,
+        I: 'static + AsyncIdentity,
     {
-        AgentBuilder {
+        Self {
             config: AgentConfig {
                 identity: Arc::new(identity),
                 ..self.config
             },
+            ..self
         }
     }
 
     /// Same as [with_identity], but provides a boxed implementation instead
-    /// of a direct type.
+    /// of a direct type. The identity signs synchronously, see [SyncIdentity].
     pub fn with_boxed_identity(self, identity: Box<dyn Identity + Send + Sync>) -> Self {
-        AgentBuilder {
+        Self {
             config: AgentConfig {
-                identity: Arc::from(identity),
+                identity: Arc::new(SyncIdentity(identity)),
                 ..self.config
             },
+            ..self
         }
     }
 
@@ -80,11 +140,55 @@ impl AgentBuilder {
     /// at the time an update or query is made. The default expiry cannot be a
     /// fixed system time.
     pub fn with_ingress_expiry(self, duration: Option<std::time::Duration>) -> Self {
-        AgentBuilder {
+        Self {
             config: AgentConfig {
                 ingress_expiry_duration: duration,
                 ..self.config
             },
+            ..self
+        }
+    }
+
+    /// Pin the root key used to verify certificates returned by the replica. The key can
+    /// either be DER-encoded or the raw 96 bytes of the BLS public key.
+    ///
+    /// By default, the Internet Computer mainnet root key is used.
+    pub fn with_root_key(self, root_key: Vec<u8>) -> Self {
+        Self {
+            config: AgentConfig {
+                root_key: Some(root_key),
+                ..self.config
+            },
+            ..self
+        }
+    }
+
+    /// Allow [Agent::fetch_root_key] to trust the status endpoint of a replica that is not
+    /// running locally. This should only be used against test networks, as a replica can
+    /// then certify anything it wants.
+    pub fn with_allow_fetch_root_key(self, allow_fetch_root_key: bool) -> Self {
+        Self {
+            config: AgentConfig {
+                allow_fetch_root_key,
+                ..self.config
+            },
+            ..self
+        }
+    }
+
+    /// Sets the maximum age of the certificates returned by the replica, based on the time
+    /// they certify. Older certificates are rejected, which protects against replayed
+    /// responses. By default, certificates older than 5 minutes are rejected.
+    ///
+    /// Use `Some(Duration::from_secs(u64::MAX))` to accept certificates of any age, e.g. to
+    /// replay recorded responses.
+    pub fn with_max_certificate_age(self, duration: Option<std::time::Duration>) -> Self {
+        Self {
+            config: AgentConfig {
+                max_certificate_age: duration,
+                ..self.config
+            },
+            ..self
         }
     }
 }
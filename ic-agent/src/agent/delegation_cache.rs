@@ -0,0 +1,78 @@
+//! A cache of the subnet keys learned from verified delegation certificates, so polling the
+//! same subnet does not need to verify its delegation every time.
+use crate::bls::bls12381::bls::PreparedPublicKey;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// What a verified delegation certificate tells about a subnet.
+pub(crate) struct SubnetKey {
+    /// The public key of the subnet, prepared for verifying certificates.
+    pub public_key: PreparedPublicKey,
+
+    /// The (inclusive) ranges of canister ids the subnet is authoritative for.
+    pub canister_ranges: Vec<(Vec<u8>, Vec<u8>)>,
+
+    /// The time (in nanoseconds since the epoch) after which the delegation needs to be
+    /// verified again.
+    pub expiry: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct DelegationCache {
+    subnets: RwLock<HashMap<Vec<u8>, Arc<SubnetKey>>>,
+}
+
+impl DelegationCache {
+    /// Returns the key of a subnet, if it was cached and has not expired at time `now`.
+    pub fn get(&self, subnet_id: &[u8], now: u64) -> Option<Arc<SubnetKey>> {
+        self.subnets
+            .read()
+            .ok()?
+            .get(subnet_id)
+            .filter(|subnet_key| subnet_key.expiry > now)
+            .cloned()
+    }
+
+    /// Cache the key of a subnet, replacing any previous entry.
+    pub fn insert(&self, subnet_id: Vec<u8>, subnet_key: SubnetKey) -> Arc<SubnetKey> {
+        let subnet_key = Arc::new(subnet_key);
+        if let Ok(mut subnets) = self.subnets.write() {
+            subnets.insert(subnet_id, subnet_key.clone());
+        }
+        subnet_key
+    }
+
+    /// Forget all the cached subnet keys, e.g. because the root key changed.
+    pub fn clear(&self) {
+        if let Ok(mut subnets) = self.subnets.write() {
+            subnets.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DelegationCache, SubnetKey};
+    use crate::bls::bls12381::bls::prepare_public_key;
+
+    #[test]
+    fn expires_entries() {
+        let pk = hex::decode("a7623a93cdb56c4d23d99c14216afaab3dfd6d4f9eb3db23d038280b6d5cb2caaee2a19dd92c9df7001dede23bf036bc0f33982dfb41e8fa9b8e96b5dc3e83d55ca4dd146c7eb2e8b6859cb5a5db815db86810b8d12cee1588b5dbf34a4dc9a5").unwrap();
+        let cache = DelegationCache::default();
+        cache.insert(
+            b"subnet".to_vec(),
+            SubnetKey {
+                public_key: prepare_public_key(&pk).unwrap(),
+                canister_ranges: vec![],
+                expiry: 1000,
+            },
+        );
+
+        assert!(cache.get(b"subnet", 999).is_some());
+        assert!(cache.get(b"subnet", 1000).is_none());
+        assert!(cache.get(b"other subnet", 999).is_none());
+
+        cache.clear();
+        assert!(cache.get(b"subnet", 999).is_none());
+    }
+}
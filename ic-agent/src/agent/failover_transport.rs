@@ -0,0 +1,434 @@
+//! A [ReplicaV2Transport] spreading requests over several replicas, and failing over to
+//! another replica when one cannot be reached.
+use crate::agent::ReplicaV2Transport;
+use crate::export::Principal;
+use crate::{AgentError, RequestId};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How a [FailoverTransport] picks the replica to send a request to. Replicas considered
+/// unhealthy are only used once all healthy replicas failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoutingPolicy {
+    /// Send each request to the next replica.
+    RoundRobin,
+    /// Send requests to the replica which answered the fastest recently.
+    LeastLatency,
+    /// Send requests to the first replica, and only use the next ones when the previous
+    /// ones are unhealthy.
+    PrimaryBackup,
+}
+
+/// The health of a replica, as observed by a [FailoverTransport].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EndpointHealth {
+    /// Whether requests are routed to this replica.
+    pub healthy: bool,
+    /// The average time the replica took to answer recently, if it answered at all.
+    pub latency: Option<Duration>,
+    /// The number of requests which failed in a row.
+    pub consecutive_failures: u32,
+    /// The number of requests sent to the replica.
+    pub requests: u64,
+    /// The number of requests which failed.
+    pub failures: u64,
+}
+
+#[derive(Default)]
+struct Health {
+    latency: Option<Duration>,
+    consecutive_failures: u32,
+    last_failure: Option<Instant>,
+    requests: u64,
+    failures: u64,
+}
+
+struct Endpoint {
+    transport: Box<dyn ReplicaV2Transport + Send + Sync>,
+    health: Mutex<Health>,
+}
+
+/// A transport wrapping the transports of several replicas.
+///
+/// Requests are routed following a [RoutingPolicy] to the replicas considered healthy. A
+/// replica becomes unhealthy after a number of requests failed in a row, either because it
+/// could not be reached or because it answered with a retryable error, and is tried again
+/// after a recovery delay. [FailoverTransport::probe] checks the status endpoint of all
+/// replicas, and should be called periodically to update their health.
+///
+/// Failures count towards the health of a replica the same way for every kind of request.
+/// When a replica fails, queries and status requests are sent to the next replica. Update
+/// calls and read_state requests are only sent to the next replica when the transport
+/// failed to communicate with the previous one; submitting the same update call to
+/// several replicas is safe, as it has the same request id.
+///
+/// ```ignore
+/// # // This test is ignored because it requires replicas to be running.
+/// use ic_agent::agent::failover_transport::{FailoverTransport, RoutingPolicy};
+/// use ic_agent::agent::http_transport::ReqwestHttpReplicaV2Transport;
+///
+/// let transport = FailoverTransport::new(RoutingPolicy::PrimaryBackup)
+///     .with_endpoint(ReqwestHttpReplicaV2Transport::create("https://primary.example.com")?)
+///     .with_endpoint(ReqwestHttpReplicaV2Transport::create("https://backup.example.com")?);
+/// let agent = ic_agent::Agent::builder().with_transport(transport).build()?;
+/// ```
+pub struct FailoverTransport {
+    endpoints: Vec<Endpoint>,
+    policy: RoutingPolicy,
+    next: AtomicUsize,
+    unhealthy_threshold: u32,
+    recovery_delay: Duration,
+}
+
+impl FailoverTransport {
+    /// Create a transport without replicas, routing requests with `policy`. Replicas are
+    /// added with [FailoverTransport::with_endpoint].
+    pub fn new(policy: RoutingPolicy) -> Self {
+        Self {
+            endpoints: Vec::new(),
+            policy,
+            next: AtomicUsize::new(0),
+            unhealthy_threshold: 3,
+            recovery_delay: Duration::from_secs(30),
+        }
+    }
+
+    /// Create a transport to the replicas at `urls`, routing requests with `policy`.
+    #[cfg(feature = "reqwest")]
+    pub fn from_urls<U: Into<String>>(
+        urls: Vec<U>,
+        policy: RoutingPolicy,
+    ) -> Result<Self, AgentError> {
+        use crate::agent::http_transport::ReqwestHttpReplicaV2Transport;
+
+        urls.into_iter()
+            .try_fold(Self::new(policy), |transport, url| {
+                Ok(transport.with_endpoint(ReqwestHttpReplicaV2Transport::create(url)?))
+            })
+    }
+
+    /// Add the transport of a replica. With [RoutingPolicy::PrimaryBackup], replicas are
+    /// used in the order they were added.
+    pub fn with_endpoint<T: 'static + ReplicaV2Transport + Send + Sync>(
+        mut self,
+        transport: T,
+    ) -> Self {
+        self.endpoints.push(Endpoint {
+            transport: Box::new(transport),
+            health: Mutex::new(Health::default()),
+        });
+        self
+    }
+
+    /// Consider a replica unhealthy once `threshold` requests to it failed in a row. The
+    /// default is 3.
+    pub fn with_unhealthy_threshold(self, threshold: u32) -> Self {
+        Self {
+            unhealthy_threshold: threshold.max(1),
+            ..self
+        }
+    }
+
+    /// Route requests to an unhealthy replica again once `delay` passed since its last
+    /// failure. The default is 30 seconds.
+    pub fn with_recovery_delay(self, delay: Duration) -> Self {
+        Self {
+            recovery_delay: delay,
+            ..self
+        }
+    }
+
+    /// The health of every replica, in the order they were added.
+    pub fn health(&self) -> Vec<EndpointHealth> {
+        self.endpoints
+            .iter()
+            .map(|endpoint| {
+                let health = endpoint.health.lock().unwrap();
+                EndpointHealth {
+                    healthy: self.is_healthy(&health),
+                    latency: health.latency,
+                    consecutive_failures: health.consecutive_failures,
+                    requests: health.requests,
+                    failures: health.failures,
+                }
+            })
+            .collect()
+    }
+
+    /// Request the status of every replica, and update their health.
+    pub async fn probe(&self) {
+        for endpoint in &self.endpoints {
+            let started = Instant::now();
+            let result = endpoint.transport.status().await;
+            self.record(endpoint, started, &result);
+        }
+    }
+
+    fn is_healthy(&self, health: &Health) -> bool {
+        health.consecutive_failures < self.unhealthy_threshold
+            || health.last_failure.map_or(true, |last_failure| {
+                last_failure.elapsed() >= self.recovery_delay
+            })
+    }
+
+    /// The indices of the replicas, in the order to try them: healthy replicas following
+    /// the routing policy, then unhealthy replicas.
+    fn route(&self) -> Vec<usize> {
+        let mut indices: Vec<usize> = (0..self.endpoints.len()).collect();
+        match self.policy {
+            RoutingPolicy::RoundRobin if !indices.is_empty() => {
+                let next = self.next.fetch_add(1, Ordering::Relaxed);
+                indices.rotate_left(next % self.endpoints.len());
+            }
+            RoutingPolicy::LeastLatency => {
+                // Replicas which never answered are tried first, to measure them.
+                indices.sort_by_key(|&i| {
+                    self.endpoints[i]
+                        .health
+                        .lock()
+                        .unwrap()
+                        .latency
+                        .unwrap_or_default()
+                });
+            }
+            _ => {}
+        }
+        // The sort is stable, so the order of the policy is kept within each group.
+        indices.sort_by_key(|&i| !self.is_healthy(&self.endpoints[i].health.lock().unwrap()));
+        indices
+    }
+
+    /// Update the health of a replica with the result of a request. Only retryable errors
+    /// count as failures of the replica.
+    fn record<T>(&self, endpoint: &Endpoint, started: Instant, result: &Result<T, AgentError>) {
+        let mut health = endpoint.health.lock().unwrap();
+        health.requests += 1;
+        match result {
+            Err(e) if e.is_retryable() => {
+                health.failures += 1;
+                health.consecutive_failures += 1;
+                health.last_failure = Some(Instant::now());
+            }
+            _ => {
+                // An exponentially weighted moving average of the latency.
+                let elapsed = started.elapsed();
+                health.latency = Some(match health.latency {
+                    Some(latency) => (latency * 4 + elapsed) / 5,
+                    None => elapsed,
+                });
+                health.consecutive_failures = 0;
+            }
+        }
+    }
+
+    /// Send a request to the replicas in the order of [FailoverTransport::route], until
+    /// one succeeds or fails with an error that should not fail over.
+    async fn send<'a, T, F>(
+        &'a self,
+        request: F,
+        fail_over: fn(&AgentError) -> bool,
+    ) -> Result<T, AgentError>
+    where
+        F: Fn(
+            &'a (dyn ReplicaV2Transport + Send + Sync),
+        ) -> Pin<Box<dyn Future<Output = Result<T, AgentError>> + Send + 'a>>,
+    {
+        let mut last_error = None;
+        for index in self.route() {
+            let endpoint = &self.endpoints[index];
+            let started = Instant::now();
+            let result = request(&*endpoint.transport).await;
+            self.record(endpoint, started, &result);
+            match result {
+                Err(e) if fail_over(&e) => last_error = Some(e),
+                result => return result,
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            AgentError::MessageError("The failover transport has no replica.".to_string())
+        }))
+    }
+}
+
+/// Whether the transport could not communicate with the replica.
+fn is_connection_error(error: &AgentError) -> bool {
+    matches!(error, AgentError::TransportError(_))
+}
+
+impl ReplicaV2Transport for FailoverTransport {
+    fn call<'a>(
+        &'a self,
+        effective_canister_id: Principal,
+        envelope: Vec<u8>,
+        request_id: RequestId,
+    ) -> Pin<Box<dyn Future<Output = Result<(), AgentError>> + Send + 'a>> {
+        Box::pin(async move {
+            self.send(
+                |transport| {
+                    transport.call(effective_canister_id.clone(), envelope.clone(), request_id)
+                },
+                is_connection_error,
+            )
+            .await
+        })
+    }
+
+    fn read_state<'a>(
+        &'a self,
+        effective_canister_id: Principal,
+        envelope: Vec<u8>,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, AgentError>> + Send + 'a>> {
+        Box::pin(async move {
+            self.send(
+                |transport| transport.read_state(effective_canister_id.clone(), envelope.clone()),
+                is_connection_error,
+            )
+            .await
+        })
+    }
+
+    fn query<'a>(
+        &'a self,
+        effective_canister_id: Principal,
+        envelope: Vec<u8>,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, AgentError>> + Send + 'a>> {
+        Box::pin(async move {
+            self.send(
+                |transport| transport.query(effective_canister_id.clone(), envelope.clone()),
+                AgentError::is_retryable,
+            )
+            .await
+        })
+    }
+
+    fn status<'a>(
+        &'a self,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, AgentError>> + Send + 'a>> {
+        Box::pin(async move {
+            self.send(|transport| transport.status(), AgentError::is_retryable)
+                .await
+        })
+    }
+
+    fn is_local(&self) -> bool {
+        !self.endpoints.is_empty()
+            && self
+                .endpoints
+                .iter()
+                .all(|endpoint| endpoint.transport.is_local())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::agent_error::HttpErrorPayload;
+    use crate::agent::test_transport::{connection_error, unavailable, TestTransport};
+
+    /// A transport answering every request with its name.
+    fn named(name: &'static str) -> TestTransport {
+        TestTransport::new(move || Ok(name.as_bytes().to_vec()))
+    }
+
+    fn query(transport: &FailoverTransport) -> Result<String, AgentError> {
+        let runtime = tokio::runtime::Runtime::new().expect("Unable to create a runtime");
+        let bytes = runtime.block_on(transport.query(Principal::management_canister(), vec![]))?;
+        Ok(String::from_utf8(bytes).unwrap())
+    }
+
+    #[test]
+    fn round_robin() -> Result<(), AgentError> {
+        let transport = FailoverTransport::new(RoutingPolicy::RoundRobin)
+            .with_endpoint(named("a"))
+            .with_endpoint(named("b"));
+
+        assert_eq!(query(&transport)?, "a");
+        assert_eq!(query(&transport)?, "b");
+        assert_eq!(query(&transport)?, "a");
+        Ok(())
+    }
+
+    #[test]
+    fn primary_backup_fails_over() -> Result<(), AgentError> {
+        let primary = TestTransport::new(|| Err(unavailable()));
+        let primary_requests = primary.requests();
+        let transport = FailoverTransport::new(RoutingPolicy::PrimaryBackup)
+            .with_unhealthy_threshold(2)
+            .with_endpoint(primary)
+            .with_endpoint(named("backup"));
+
+        assert_eq!(query(&transport)?, "backup");
+        assert_eq!(query(&transport)?, "backup");
+        assert_eq!(primary_requests.lock().unwrap().len(), 2);
+
+        // The primary is now unhealthy, and not tried first anymore.
+        assert_eq!(query(&transport)?, "backup");
+        assert_eq!(primary_requests.lock().unwrap().len(), 2);
+        let health = transport.health();
+        assert!(!health[0].healthy);
+        assert_eq!(health[0].failures, 2);
+        assert!(health[1].healthy);
+        Ok(())
+    }
+
+    #[test]
+    fn calls_only_fail_over_on_connection_errors() {
+        let runtime = tokio::runtime::Runtime::new().expect("Unable to create a runtime");
+        let request_id = RequestId::new(&[0; 32]);
+        let call = |transport: FailoverTransport| {
+            runtime.block_on(transport.call(Principal::management_canister(), vec![], request_id))
+        };
+
+        let transport = FailoverTransport::new(RoutingPolicy::PrimaryBackup)
+            .with_endpoint(TestTransport::new(|| Err(connection_error())))
+            .with_endpoint(named("backup"));
+        assert!(call(transport).is_ok());
+
+        let transport = FailoverTransport::new(RoutingPolicy::PrimaryBackup)
+            .with_endpoint(TestTransport::new(|| Err(unavailable())))
+            .with_endpoint(named("backup"));
+        assert!(matches!(
+            call(transport),
+            Err(AgentError::HttpError(HttpErrorPayload { status: 503, .. }))
+        ));
+    }
+
+    #[test]
+    fn calls_update_health_without_failing_over() {
+        let runtime = tokio::runtime::Runtime::new().expect("Unable to create a runtime");
+        let transport = FailoverTransport::new(RoutingPolicy::PrimaryBackup)
+            .with_unhealthy_threshold(1)
+            .with_endpoint(TestTransport::new(|| Err(unavailable())))
+            .with_endpoint(named("backup"));
+        let result = runtime.block_on(transport.call(
+            Principal::management_canister(),
+            vec![],
+            RequestId::new(&[0; 32]),
+        ));
+        assert!(result.is_err());
+
+        let health = transport.health();
+        assert!(!health[0].healthy);
+        assert_eq!(health[0].failures, 1);
+        assert_eq!(health[1].requests, 0);
+    }
+
+    #[test]
+    fn probe_updates_health() {
+        let transport = FailoverTransport::new(RoutingPolicy::LeastLatency)
+            .with_unhealthy_threshold(1)
+            .with_endpoint(TestTransport::new(|| Err(connection_error())))
+            .with_endpoint(named("up"));
+
+        let runtime = tokio::runtime::Runtime::new().expect("Unable to create a runtime");
+        runtime.block_on(transport.probe());
+        let health = transport.health();
+        assert!(!health[0].healthy);
+        assert!(health[1].healthy);
+        assert!(health[1].latency.is_some());
+        assert_eq!(transport.route(), vec![1, 0]);
+    }
+}
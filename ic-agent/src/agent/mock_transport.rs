@@ -0,0 +1,756 @@
+//! A [ReplicaV2Transport] answering requests in memory, to test code using an [Agent]
+//! without a replica.
+//!
+//! The state of the calls is certified like a replica would, with BLS certificates signed
+//! by a test root key, optionally delegated to a subnet. The [Agent] thus verifies the
+//! responses as it would verify those of a replica, once built with
+//! [with_root_key](super::AgentBuilder::with_root_key) and the key of the transport.
+use crate::agent::replica_api::{
+    CallReply, Certificate, Delegation, QueryResponse, ReadStateResponse,
+};
+use crate::agent::response_authentication::{der_encode_bls_key, initialize_bls};
+use crate::agent::verify::{decode_envelope, verify_envelope};
+use crate::agent::{EnvelopeContent, RejectCode, ReplicaV2Transport};
+use crate::bls::bls12381::bls;
+use crate::export::Principal;
+use crate::hash_tree::{HashTree, Label};
+use crate::{AgentError, RequestId};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The seed of the root key of a [MockTransport].
+const ROOT_KEY_SEED: &[u8] = b"ic-agent mock transport root key";
+
+/// The seed of the key of the subnet a [MockTransport] delegates to.
+const SUBNET_KEY_SEED: &[u8] = b"ic-agent mock transport subnet key";
+
+/// The answer of a canister method registered on a [MockTransport].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MockReply {
+    /// The method replied with the given Candid-encoded value.
+    Reply(Vec<u8>),
+    /// The call was rejected.
+    Reject {
+        reject_code: RejectCode,
+        reject_message: String,
+    },
+}
+
+type Handler = Arc<dyn Fn(&[u8]) -> MockReply + Send + Sync>;
+
+/// A BLS key pair, certifying state trees.
+struct BlsKey {
+    secret_key: [u8; bls::BGS],
+    public_key: [u8; 2 * bls::BFS],
+}
+
+impl BlsKey {
+    fn from_seed(seed: &[u8]) -> Self {
+        initialize_bls().expect("Cannot initialize BLS.");
+        let mut key = BlsKey {
+            secret_key: [0; bls::BGS],
+            public_key: [0; 2 * bls::BFS],
+        };
+        bls::key_pair_generate(seed, &mut key.secret_key, &mut key.public_key);
+        key
+    }
+
+    /// The DER encoding of the public key.
+    fn der(&self) -> Vec<u8> {
+        der_encode_bls_key(self.public_key.to_vec()).expect("Invalid BLS public key.")
+    }
+
+    /// Sign the tree holding the given leaves, and return the CBOR-encoded certificate.
+    fn certify(&self, leaves: &[(Vec<Label>, Vec<u8>)], delegation: Option<Delegation>) -> Vec<u8> {
+        let tree = HashTree::from_leaves(leaves);
+        let mut message = super::IC_STATE_ROOT_DOMAIN_SEPARATOR.to_vec();
+        message.extend_from_slice(&tree.digest());
+        let mut signature = [0; bls::BFS];
+        bls::core_sign(&mut signature, &message, &self.secret_key);
+
+        let certificate = Certificate {
+            tree,
+            signature: signature.to_vec(),
+            delegation,
+        };
+        serde_cbor::to_vec(&certificate).expect("Cannot encode the certificate.")
+    }
+}
+
+/// The subnet the root key delegates to, and the canisters it is authoritative for.
+struct MockSubnet {
+    subnet_id: Principal,
+    key: BlsKey,
+    canister_ranges: Vec<(Principal, Principal)>,
+    delegation_age: Duration,
+}
+
+/// A call submitted to a [MockTransport].
+struct MockCall {
+    reply: MockReply,
+    processing_polls: usize,
+}
+
+/// The status of a submitted call, as reported to a status request.
+enum CallStatus {
+    Processing,
+    Done(MockReply),
+}
+
+/// A [ReplicaV2Transport] answering calls and queries with the handlers registered for the
+/// canister methods, instead of sending them to a replica.
+///
+/// The envelopes of requests are verified, and invalid ones are rejected with an HTTP 400
+/// error. Calls are answered as soon as they are submitted, and their status is certified
+/// by the test root key of the transport, which the [Agent] needs to be given:
+///
+/// ```ignore
+/// # use ic_agent::agent::mock_transport::{MockReply, MockTransport};
+/// # use ic_agent::Agent;
+/// # use ic_agent::export::Principal;
+/// let canister_id = Principal::from_text("rwlgt-iiaaa-aaaaa-aaaaa-cai")?;
+/// let transport = MockTransport::new()
+///     .with_handler(&canister_id, "greet", |arg| MockReply::Reply(arg.to_vec()));
+/// let agent = Agent::builder()
+///     .with_root_key(transport.root_key())
+///     .with_transport(transport)
+///     .build()?;
+/// ```
+pub struct MockTransport {
+    root_key: BlsKey,
+    subnet: Option<MockSubnet>,
+    handlers: HashMap<(Principal, String), Handler>,
+    processing_polls: usize,
+    drop_calls: bool,
+    calls: Mutex<BTreeMap<RequestId, MockCall>>,
+}
+
+impl Default for MockTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockTransport {
+    /// Create a transport without any canister method, certifying with its test root key.
+    pub fn new() -> Self {
+        Self {
+            root_key: BlsKey::from_seed(ROOT_KEY_SEED),
+            subnet: None,
+            handlers: HashMap::new(),
+            processing_polls: 0,
+            drop_calls: false,
+            calls: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// The DER-encoded root key certifying the responses, to be passed to
+    /// [AgentBuilder::with_root_key](super::AgentBuilder::with_root_key).
+    pub fn root_key(&self) -> Vec<u8> {
+        self.root_key.der()
+    }
+
+    /// Answer the calls and queries of the method `method_name` of `canister_id` with
+    /// `handler`, which receives the argument of the call.
+    pub fn with_handler<F>(mut self, canister_id: &Principal, method_name: &str, handler: F) -> Self
+    where
+        F: 'static + Fn(&[u8]) -> MockReply + Send + Sync,
+    {
+        self.handlers.insert(
+            (canister_id.clone(), method_name.to_string()),
+            Arc::new(handler),
+        );
+        self
+    }
+
+    /// Certify responses with the key of a subnet, delegated by the root key for the
+    /// canister ids in the inclusive `canister_ranges`.
+    pub fn with_subnet_delegation(
+        self,
+        subnet_id: Principal,
+        canister_ranges: Vec<(Principal, Principal)>,
+    ) -> Self {
+        Self {
+            subnet: Some(MockSubnet {
+                subnet_id,
+                key: BlsKey::from_seed(SUBNET_KEY_SEED),
+                canister_ranges,
+                delegation_age: Duration::from_secs(0),
+            }),
+            ..self
+        }
+    }
+
+    /// Certify the delegation to the subnet as of `age` ago, as the root key delegates to
+    /// a subnet long before the subnet answers requests. Has no effect without a subnet
+    /// delegation.
+    pub fn with_delegation_age(mut self, age: Duration) -> Self {
+        if let Some(subnet) = &mut self.subnet {
+            subnet.delegation_age = age;
+        }
+        self
+    }
+
+    /// Report calls as processing to the given number of status requests, before reporting
+    /// their reply. By default, calls are replied at once.
+    pub fn with_processing_polls(self, processing_polls: usize) -> Self {
+        Self {
+            processing_polls,
+            ..self
+        }
+    }
+
+    /// Accept calls without ever executing them, as a replica dropping requests from its
+    /// ingress queue does, so their status stays unknown.
+    pub fn with_dropped_calls(self) -> Self {
+        Self {
+            drop_calls: true,
+            ..self
+        }
+    }
+
+    /// Decode and verify an envelope, rejecting invalid ones as a replica would.
+    fn verified_content(envelope: &[u8]) -> Result<(RequestId, EnvelopeContent), AgentError> {
+        let envelope = decode_envelope(envelope).map_err(bad_request)?;
+        let request_id = verify_envelope(&envelope).map_err(bad_request)?;
+        Ok((request_id, envelope.content))
+    }
+
+    fn handle(&self, canister_id: &Principal, method_name: &str, arg: &[u8]) -> MockReply {
+        match self
+            .handlers
+            .get(&(canister_id.clone(), method_name.to_string()))
+        {
+            Some(handler) => handler(arg),
+            None => MockReply::Reject {
+                reject_code: RejectCode::DestinationInvalid,
+                reject_message: format!("Canister {} has no method {}.", canister_id, method_name),
+            },
+        }
+    }
+
+    /// The status of a submitted call, or `None` if it was not submitted.
+    fn call_status(&self, request_id: &RequestId) -> Option<CallStatus> {
+        let mut calls = self.calls.lock().unwrap();
+        let call = calls.get_mut(request_id)?;
+        if call.processing_polls > 0 {
+            call.processing_polls -= 1;
+            Some(CallStatus::Processing)
+        } else {
+            Some(CallStatus::Done(call.reply.clone()))
+        }
+    }
+
+    /// Certify the status of the calls read by `paths`, with the current time.
+    fn read_state_certificate(&self, paths: &[Vec<Label>]) -> Vec<u8> {
+        let mut leaves: Vec<(Vec<Label>, Vec<u8>)> =
+            vec![(vec!["time".into()], leb128_encode(now()))];
+        let mut read_ids = Vec::new();
+        for path in paths {
+            if let [request_status, request_id, ..] = path.as_slice() {
+                if request_status.as_bytes() != b"request_status"
+                    || read_ids.contains(request_id)
+                    || request_id.as_bytes().len() != 32
+                {
+                    continue;
+                }
+                read_ids.push(request_id.clone());
+
+                let mut id = [0; 32];
+                id.copy_from_slice(request_id.as_bytes());
+                let status_leaves = match self.call_status(&RequestId::new(&id)) {
+                    None => continue,
+                    Some(CallStatus::Processing) => vec![("status", b"processing".to_vec())],
+                    Some(CallStatus::Done(MockReply::Reply(arg))) => {
+                        vec![("reply", arg), ("status", b"replied".to_vec())]
+                    }
+                    Some(CallStatus::Done(MockReply::Reject {
+                        reject_code,
+                        reject_message,
+                    })) => vec![
+                        ("reject_code", leb128_encode(reject_code.into())),
+                        ("reject_message", reject_message.into_bytes()),
+                        ("status", b"rejected".to_vec()),
+                    ],
+                };
+                for (label, value) in status_leaves {
+                    leaves.push((
+                        vec!["request_status".into(), request_id.clone(), label.into()],
+                        value,
+                    ));
+                }
+            }
+        }
+
+        match &self.subnet {
+            None => self.root_key.certify(&leaves, None),
+            Some(subnet) => {
+                let delegation = Delegation {
+                    subnet_id: subnet.subnet_id.as_slice().to_vec(),
+                    certificate: self.delegation_certificate(subnet),
+                };
+                subnet.key.certify(&leaves, Some(delegation))
+            }
+        }
+    }
+
+    /// Certify the key of the subnet and its canister ranges with the root key.
+    fn delegation_certificate(&self, subnet: &MockSubnet) -> Vec<u8> {
+        let canister_ranges: Vec<(serde_bytes::ByteBuf, serde_bytes::ByteBuf)> = subnet
+            .canister_ranges
+            .iter()
+            .map(|(low, high)| {
+                (
+                    serde_bytes::ByteBuf::from(low.as_slice().to_vec()),
+                    serde_bytes::ByteBuf::from(high.as_slice().to_vec()),
+                )
+            })
+            .collect();
+        let subnet_path = |label: &str| -> Vec<Label> {
+            vec![
+                "subnet".into(),
+                subnet.subnet_id.as_slice().into(),
+                label.into(),
+            ]
+        };
+        let leaves = vec![
+            (
+                subnet_path("canister_ranges"),
+                serde_cbor::to_vec(&canister_ranges).expect("Cannot encode the canister ranges."),
+            ),
+            (subnet_path("public_key"), subnet.key.der()),
+            (
+                vec!["time".into()],
+                leb128_encode(now().saturating_sub(subnet.delegation_age.as_nanos() as u64)),
+            ),
+        ];
+        self.root_key.certify(&leaves, None)
+    }
+}
+
+fn bad_request<E: std::error::Error>(error: E) -> AgentError {
+    AgentError::HttpError(crate::agent::agent_error::HttpErrorPayload {
+        status: 400,
+        content_type: Some("text/plain".to_string()),
+        content: error.to_string().into_bytes(),
+    })
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("Time wrapped around.")
+        .as_nanos() as u64
+}
+
+fn leb128_encode(value: u64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    leb128::write::unsigned(&mut bytes, value).expect("Cannot write to a vector.");
+    bytes
+}
+
+impl ReplicaV2Transport for MockTransport {
+    fn call<'a>(
+        &'a self,
+        _effective_canister_id: Principal,
+        envelope: Vec<u8>,
+        _request_id: RequestId,
+    ) -> Pin<Box<dyn Future<Output = Result<(), AgentError>> + Send + 'a>> {
+        Box::pin(async move {
+            let (request_id, content) = Self::verified_content(&envelope)?;
+            let reply = match content {
+                EnvelopeContent::Call {
+                    canister_id,
+                    method_name,
+                    arg,
+                    ..
+                } => self.handle(&canister_id, &method_name, &arg),
+                _ => return Err(bad_request(UnexpectedRequestType)),
+            };
+            if self.drop_calls {
+                return Ok(());
+            }
+            self.calls
+                .lock()
+                .unwrap()
+                .entry(request_id)
+                .or_insert(MockCall {
+                    reply,
+                    processing_polls: self.processing_polls,
+                });
+            Ok(())
+        })
+    }
+
+    fn read_state<'a>(
+        &'a self,
+        _effective_canister_id: Principal,
+        envelope: Vec<u8>,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, AgentError>> + Send + 'a>> {
+        Box::pin(async move {
+            let paths = match Self::verified_content(&envelope)? {
+                (_, EnvelopeContent::ReadState { paths, .. }) => paths,
+                _ => return Err(bad_request(UnexpectedRequestType)),
+            };
+            let response = ReadStateResponse {
+                certificate: self.read_state_certificate(&paths),
+            };
+            serde_cbor::to_vec(&response).map_err(AgentError::InvalidCborData)
+        })
+    }
+
+    fn query<'a>(
+        &'a self,
+        _effective_canister_id: Principal,
+        envelope: Vec<u8>,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, AgentError>> + Send + 'a>> {
+        Box::pin(async move {
+            let response = match Self::verified_content(&envelope)? {
+                (
+                    _,
+                    EnvelopeContent::Query {
+                        canister_id,
+                        method_name,
+                        arg,
+                        ..
+                    },
+                ) => match self.handle(&canister_id, &method_name, &arg) {
+                    MockReply::Reply(arg) => QueryResponse::Replied {
+                        reply: CallReply { arg },
+                    },
+                    MockReply::Reject {
+                        reject_code,
+                        reject_message,
+                    } => QueryResponse::Rejected {
+                        reject_code,
+                        reject_message,
+                    },
+                },
+                _ => return Err(bad_request(UnexpectedRequestType)),
+            };
+            serde_cbor::to_vec(&response).map_err(AgentError::InvalidCborData)
+        })
+    }
+
+    fn status<'a>(
+        &'a self,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, AgentError>> + Send + 'a>> {
+        #[derive(Serialize)]
+        struct MockStatus {
+            ic_api_version: &'static str,
+            #[serde(with = "serde_bytes")]
+            root_key: Vec<u8>,
+        }
+
+        let status = MockStatus {
+            ic_api_version: "0.18.0",
+            root_key: self.root_key(),
+        };
+        Box::pin(async move { serde_cbor::to_vec(&status).map_err(AgentError::InvalidCborData) })
+    }
+
+    fn is_local(&self) -> bool {
+        true
+    }
+}
+
+/// The error returned for a request sent to the endpoint of another type of request.
+#[derive(thiserror::Error, Debug)]
+#[error("The request type does not match the endpoint.")]
+struct UnexpectedRequestType;
+
+#[cfg(test)]
+mod tests {
+    use super::{now, MockReply, MockTransport};
+    use crate::agent::{
+        Backoff, Certificate, RejectCode, Replied, RequestStatusResponse, SignedQuery,
+        SignedRequestStatus,
+    };
+    use crate::export::Principal;
+    use crate::hash_tree::{Label, LookupResult};
+    use crate::identity::{AsyncIdentity, BasicIdentity};
+    use crate::{Agent, AgentError};
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+
+    fn canister_id() -> Principal {
+        Principal::from_text("rwlgt-iiaaa-aaaaa-aaaaa-cai").unwrap()
+    }
+
+    fn transport() -> MockTransport {
+        MockTransport::new()
+            .with_handler(&canister_id(), "greet", |arg| {
+                MockReply::Reply([b"Hello, ", arg].concat())
+            })
+            .with_handler(&canister_id(), "fail", |_| MockReply::Reject {
+                reject_code: RejectCode::CanisterError,
+                reject_message: "Canister trapped.".to_string(),
+            })
+    }
+
+    fn agent(transport: MockTransport) -> Result<Agent, AgentError> {
+        Agent::builder()
+            .with_root_key(transport.root_key())
+            .with_transport(transport)
+            .build()
+    }
+
+    fn call_and_wait(agent: &Agent, method_name: &str) -> Result<Vec<u8>, AgentError> {
+        let runtime = tokio::runtime::Runtime::new().expect("Unable to create a runtime");
+        runtime.block_on(
+            agent
+                .update(&canister_id(), method_name)
+                .with_arg(b"World")
+                .call_and_wait(
+                    Backoff::throttle(Duration::from_millis(5))
+                        .with_timeout(Duration::from_secs(5)),
+                ),
+        )
+    }
+
+    #[test]
+    fn call_and_wait_replied() -> Result<(), AgentError> {
+        let agent = agent(transport().with_processing_polls(2))?;
+        assert_eq!(call_and_wait(&agent, "greet")?, b"Hello, World");
+        Ok(())
+    }
+
+    #[test]
+    fn call_and_wait_expired() -> Result<(), AgentError> {
+        let agent = agent(transport().with_dropped_calls())?;
+        let runtime = tokio::runtime::Runtime::new().expect("Unable to create a runtime");
+        let call_and_wait = |expiry: SystemTime| {
+            runtime.block_on(
+                agent
+                    .update(&canister_id(), "greet")
+                    .expire_at(expiry)
+                    .call_and_wait(
+                        Backoff::throttle(Duration::from_millis(5))
+                            .with_timeout(Duration::from_millis(100)),
+                    ),
+            )
+        };
+
+        // The replica certifies a time past the expiry without knowing the request.
+        assert!(matches!(
+            call_and_wait(SystemTime::now() - Duration::from_secs(60)),
+            Err(AgentError::RequestExpired { .. })
+        ));
+        // Before the expiry, the request may still be accepted.
+        assert!(matches!(
+            call_and_wait(SystemTime::now() + Duration::from_secs(60)),
+            Err(AgentError::TimeoutWaitingForResponse())
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn call_and_wait_rejected() -> Result<(), AgentError> {
+        let agent = agent(transport())?;
+        match call_and_wait(&agent, "fail") {
+            Err(AgentError::ReplicaError {
+                reject_code: RejectCode::CanisterError,
+                reject_message,
+            }) => assert_eq!(reject_message, "Canister trapped."),
+            result => panic!("Unexpected result {:?}", result),
+        }
+        assert!(matches!(
+            call_and_wait(&agent, "unknown"),
+            Err(AgentError::ReplicaError {
+                reject_code: RejectCode::DestinationInvalid,
+                ..
+            })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn query() -> Result<(), AgentError> {
+        let agent = agent(transport())?;
+        let runtime = tokio::runtime::Runtime::new().expect("Unable to create a runtime");
+        let reply = runtime.block_on(
+            agent
+                .query(&canister_id(), "greet")
+                .with_arg(b"World")
+                .call(),
+        )?;
+        assert_eq!(reply, b"Hello, World");
+        Ok(())
+    }
+
+    #[test]
+    fn sign_and_submit_query() -> Result<(), AgentError> {
+        let agent = agent(transport())?;
+        let runtime = tokio::runtime::Runtime::new().expect("Unable to create a runtime");
+        let signed = runtime.block_on(
+            agent
+                .query(&canister_id(), "greet")
+                .with_arg(b"World")
+                .sign(),
+        )?;
+
+        // The signed query can be stored, and submitted later.
+        let signed: SignedQuery = serde_cbor::from_slice(&serde_cbor::to_vec(&signed)?)?;
+        let reply = runtime.block_on(agent.submit_query(&signed))?;
+        assert_eq!(reply, b"Hello, World");
+        Ok(())
+    }
+
+    #[test]
+    fn sign_and_submit_request_status() -> Result<(), AgentError> {
+        let agent = agent(transport().with_processing_polls(1))?;
+        let runtime = tokio::runtime::Runtime::new().expect("Unable to create a runtime");
+        let signed_update = runtime.block_on(
+            agent
+                .update(&canister_id(), "greet")
+                .with_arg(b"World")
+                .sign(),
+        )?;
+        let request_id = runtime.block_on(agent.submit_update(&signed_update))?;
+        let signed =
+            runtime.block_on(agent.sign_request_status(request_id, canister_id(), None))?;
+
+        // The signed request status can be stored, and submitted any number of times.
+        let signed: SignedRequestStatus = serde_cbor::from_slice(&serde_cbor::to_vec(&signed)?)?;
+        assert_eq!(
+            runtime.block_on(agent.submit_request_status(&signed))?,
+            RequestStatusResponse::Processing
+        );
+        assert_eq!(
+            runtime.block_on(agent.submit_request_status(&signed))?,
+            RequestStatusResponse::Replied {
+                reply: Replied::CallReplied(b"Hello, World".to_vec())
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn sign_request_status_as() -> Result<(), AgentError> {
+        let agent = agent(transport())?;
+        let identity: Arc<dyn AsyncIdentity> =
+            Arc::new(BasicIdentity::generate().expect("Cannot generate a key."));
+        let runtime = tokio::runtime::Runtime::new().expect("Unable to create a runtime");
+        let signed_update = runtime.block_on(
+            agent
+                .update(&canister_id(), "greet")
+                .with_arg(b"World")
+                .with_identity(identity.clone())
+                .sign(),
+        )?;
+        let request_id = runtime.block_on(agent.submit_update(&signed_update))?;
+
+        // The status is read by the sender of the call, until the call expires.
+        let signed = runtime.block_on(agent.sign_request_status_as(
+            &*identity,
+            request_id,
+            canister_id(),
+            Some(signed_update.ingress_expiry),
+        ))?;
+        assert_eq!(signed.sender, signed_update.sender);
+        assert_eq!(signed.ingress_expiry, signed_update.ingress_expiry);
+        assert_eq!(
+            runtime.block_on(agent.submit_request_status(&signed))?,
+            RequestStatusResponse::Replied {
+                reply: Replied::CallReplied(b"Hello, World".to_vec())
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn read_state() -> Result<(), AgentError> {
+        let agent = agent(transport())?;
+        let runtime = tokio::runtime::Runtime::new().expect("Unable to create a runtime");
+        let request_id = runtime.block_on(
+            agent
+                .update(&canister_id(), "greet")
+                .with_arg(b"World")
+                .call(),
+        )?;
+        let path = |label: &str| -> Vec<Label> {
+            vec![
+                "request_status".into(),
+                request_id.to_vec().into(),
+                label.into(),
+            ]
+        };
+        let cert = runtime.block_on(agent.read_state(vec![path("reply")], canister_id()))?;
+
+        // The certificate can be stored, and looked up later.
+        let cert: Certificate = serde_cbor::from_slice(&serde_cbor::to_vec(&cert)?)?;
+        assert_eq!(
+            cert.lookup(path("reply")),
+            LookupResult::Found(&b"Hello, World"[..])
+        );
+        assert!(matches!(
+            cert.lookup(vec![Label::from("time")]),
+            LookupResult::Found(_)
+        ));
+        assert_eq!(cert.lookup(path("reject_code")), LookupResult::Absent);
+        Ok(())
+    }
+
+    #[test]
+    fn delegation() -> Result<(), AgentError> {
+        let subnet_id = Principal::from_text("2vxsx-fae")?;
+        let agent = agent(
+            transport()
+                .with_subnet_delegation(subnet_id.clone(), vec![(canister_id(), canister_id())]),
+        )?;
+        assert_eq!(call_and_wait(&agent, "greet")?, b"Hello, World");
+
+        let other_canister_id = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai")?;
+        let agent = agent(transport().with_subnet_delegation(
+            subnet_id,
+            vec![(other_canister_id.clone(), other_canister_id)],
+        ))?;
+        assert!(matches!(
+            call_and_wait(&agent, "greet"),
+            Err(AgentError::CertificateNotAuthorized { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn delegation_cached() -> Result<(), AgentError> {
+        let subnet_id = Principal::from_text("2vxsx-fae")?;
+        let agent = agent(
+            transport()
+                .with_subnet_delegation(subnet_id.clone(), vec![(canister_id(), canister_id())])
+                .with_delegation_age(Duration::from_secs(24 * 60 * 60)),
+        )?;
+        assert_eq!(call_and_wait(&agent, "greet")?, b"Hello, World");
+
+        // The delegation is older than the maximum certificate age, but was just verified.
+        assert!(agent
+            .delegation_cache
+            .get(subnet_id.as_slice(), now())
+            .is_some());
+        assert_eq!(call_and_wait(&agent, "greet")?, b"Hello, World");
+        Ok(())
+    }
+
+    #[test]
+    fn wrong_root_key() -> Result<(), AgentError> {
+        let agent = Agent::builder().with_transport(transport()).build()?;
+        assert!(matches!(
+            call_and_wait(&agent, "greet"),
+            Err(AgentError::CertificateVerificationFailed())
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn fetch_root_key() -> Result<(), AgentError> {
+        let agent = Agent::builder().with_transport(transport()).build()?;
+        let runtime = tokio::runtime::Runtime::new().expect("Unable to create a runtime");
+        runtime.block_on(agent.fetch_root_key())?;
+        assert_eq!(call_and_wait(&agent, "greet")?, b"Hello, World");
+        Ok(())
+    }
+}
@@ -2,36 +2,49 @@
 pub(crate) mod agent_config;
 pub mod agent_error;
 pub(crate) mod builder;
+mod delegation_cache;
+pub mod failover_transport;
 pub mod http_transport;
+pub mod mock_transport;
 pub(crate) mod nonce;
+pub mod polling;
+pub mod recording_transport;
 pub(crate) mod replica_api;
 pub(crate) mod response;
 mod response_authentication;
+pub mod signed;
+pub mod unix_socket_transport;
+pub mod verify;
 
 pub mod status;
 pub use agent_config::AgentConfig;
-pub use agent_error::AgentError;
+pub use agent_error::{AgentError, AgentErrorKind};
 pub use builder::AgentBuilder;
 pub use nonce::NonceFactory;
-pub use response::{Replied, RequestStatusResponse};
+pub use polling::{Backoff, PollingStrategy, WaiterCompat};
+pub use replica_api::{Certificate, Delegation, Envelope, EnvelopeContent};
+pub use response::{RejectCode, Replied, RequestStatusResponse};
+pub use signed::{SignedQuery, SignedRequestStatus, SignedUpdate};
 
 #[cfg(test)]
 mod agent_test;
+#[cfg(test)]
+mod test_transport;
 
+use crate::agent::delegation_cache::{DelegationCache, SubnetKey};
 use crate::agent::replica_api::{
-    CallRequestContent, Certificate, Delegation, Envelope, QueryContent, ReadStateContent,
-    ReadStateResponse,
+    CallRequestContent, Envelope, QueryContent, ReadStateContent, ReadStateResponse,
 };
 use crate::export::Principal;
 use crate::hash_tree::Label;
-use crate::identity::Identity;
+use crate::identity::AsyncIdentity;
 use crate::{to_request_id, RequestId};
-use delay::Waiter;
 use serde::Serialize;
 use status::Status;
 
 use crate::agent::response_authentication::{
-    extract_der, initialize_bls, lookup_canister_info, lookup_request_status, lookup_value,
+    der_encode_bls_key, extract_der, initialize_bls, lookup_canister_info, lookup_canister_ranges,
+    lookup_request_status, lookup_time, lookup_value,
 };
 use crate::bls::bls12381::bls;
 use std::convert::TryFrom;
@@ -43,6 +56,12 @@ use std::time::Duration;
 const IC_REQUEST_DOMAIN_SEPARATOR: &[u8; 11] = b"\x0Aic-request";
 const IC_STATE_ROOT_DOMAIN_SEPARATOR: &[u8; 14] = b"\x0Dic-state-root";
 
+/// How far in the future the time of a certificate can be, to account for clock drift.
+const CERTIFICATE_PERMITTED_DRIFT: Duration = Duration::from_secs(60);
+
+/// The DER-encoded root key of the Internet Computer mainnet.
+const IC_ROOT_KEY: &[u8; 133] = b"\x30\x81\x82\x30\x1d\x06\x0d\x2b\x06\x01\x04\x01\x82\xdc\x7c\x05\x03\x01\x02\x01\x06\x0c\x2b\x06\x01\x04\x01\x82\xdc\x7c\x05\x03\x02\x01\x03\x61\x00\x81\x4c\x0e\x6e\xc7\x1f\xab\x58\x3b\x08\xbd\x81\x37\x3c\x25\x5c\x3c\x37\x1b\x2e\x84\x86\x3c\x98\xa4\xf1\xe0\x8b\x74\x23\x5d\x14\xfb\x5d\x9c\x0c\xd5\x46\xd9\x68\x5f\x91\x3a\x0c\x0b\x2c\xc5\x34\x15\x83\xbf\x4b\x43\x92\xe4\x67\xdb\x96\xd6\x5b\x9b\xb4\xcb\x71\x71\x12\xf8\x47\x2e\x0d\x5a\x4d\x14\x50\x5f\xfd\x74\x84\xb0\x12\x91\x09\x1c\x5f\x87\xb9\x88\x83\x46\x3f\x98\x09\x1a\x0b\xaa\xae";
+
 /// A facade that connects to a Replica and does requests. These requests can be of any type
 /// (does not have to be HTTP). This trait is to inverse the control from the Agent over its
 /// connection code, and to resolve any direct dependencies to tokio or HTTP code from this
@@ -90,6 +109,13 @@ pub trait ReplicaV2Transport {
     fn status<'a>(
         &'a self,
     ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, AgentError>> + Send + 'a>>;
+
+    /// Whether the replica behind this transport runs on the local machine. The [Agent]
+    /// only trusts the root key returned by the status endpoint of a local replica,
+    /// unless explicitly configured otherwise.
+    fn is_local(&self) -> bool {
+        false
+    }
 }
 
 /// A low level Agent to make calls to a Replica endpoint.
@@ -112,7 +138,7 @@ pub trait ReplicaV2Transport {
 ///   canister_id: candid::Principal,
 /// }
 ///
-/// # fn create_identity() -> impl ic_agent::Identity {
+/// # fn create_identity() -> impl ic_agent::AsyncIdentity {
 /// #     let rng = ring::rand::SystemRandom::new();
 /// #     let key_pair = ring::signature::Ed25519KeyPair::generate_pkcs8(&rng)
 /// #         .expect("Could not generate a key pair.");
@@ -133,10 +159,8 @@ pub trait ReplicaV2Transport {
 ///   agent.fetch_root_key().await?;
 ///   let management_canister_id = Principal::from_text("aaaaa-aa")?;
 ///
-///   let waiter = delay::Delay::builder()
-///     .throttle(std::time::Duration::from_millis(500))
-///     .timeout(std::time::Duration::from_secs(60 * 5))
-///     .build();
+///   let waiter = ic_agent::agent::Backoff::throttle(std::time::Duration::from_millis(500))
+///     .with_timeout(std::time::Duration::from_secs(60 * 5));
 ///
 ///   // Create a call to the management canister to create a new canister ID,
 ///   // and wait for a result.
@@ -161,9 +185,12 @@ pub trait ReplicaV2Transport {
 #[derive(Clone)]
 pub struct Agent {
     nonce_factory: NonceFactory,
-    identity: Arc<dyn Identity + Send + Sync>,
+    identity: Arc<dyn AsyncIdentity>,
     ingress_expiry_duration: Duration,
-    root_key: Arc<RwLock<Option<Vec<u8>>>>,
+    root_key: Arc<RwLock<Vec<u8>>>,
+    allow_fetch_root_key: bool,
+    max_certificate_age: Duration,
+    delegation_cache: Arc<DelegationCache>,
     transport: Arc<dyn ReplicaV2Transport + Send + Sync>,
 }
 
@@ -178,13 +205,23 @@ impl Agent {
     pub fn new(config: AgentConfig) -> Result<Agent, AgentError> {
         initialize_bls()?;
 
+        let root_key = match config.root_key {
+            Some(root_key) => der_encode_bls_key(root_key)?,
+            None => IC_ROOT_KEY.to_vec(),
+        };
+
         Ok(Agent {
             nonce_factory: config.nonce_factory,
             identity: config.identity,
             ingress_expiry_duration: config
                 .ingress_expiry_duration
                 .unwrap_or_else(|| Duration::from_secs(300)),
-            root_key: Arc::new(RwLock::new(None)),
+            root_key: Arc::new(RwLock::new(root_key)),
+            allow_fetch_root_key: config.allow_fetch_root_key,
+            max_certificate_age: config
+                .max_certificate_age
+                .unwrap_or_else(|| Duration::from_secs(300)),
+            delegation_cache: Arc::new(DelegationCache::default()),
             transport: config
                 .transport
                 .ok_or_else(AgentError::MissingReplicaTransport)?,
@@ -202,26 +239,37 @@ impl Agent {
     /// to contact multiple replicas.
     ///
     /// The root key is necessary for validating state and certificates sent by the replica.
-    /// By default, it is set to [None] and validating methods will return an error.
+    /// By default, it is set to the Internet Computer mainnet root key, and this method
+    /// refuses to replace it with the key of a replica that is not running locally, unless
+    /// [`AgentBuilder::with_allow_fetch_root_key`] was used.
     pub async fn fetch_root_key(&self) -> Result<(), AgentError> {
+        if !self.allow_fetch_root_key && !self.transport.is_local() {
+            return Err(AgentError::RootKeyFetchNotAllowed());
+        }
+
         let status = self.status().await?;
         let root_key = status
             .root_key
             .clone()
             .ok_or(AgentError::NoRootKeyInStatus(status))?;
+        self.set_root_key(root_key)
+    }
+
+    /// Replace the root key used to verify certificates. The key can either be DER-encoded
+    /// or the raw bytes of the BLS public key.
+    pub fn set_root_key(&self, root_key: Vec<u8>) -> Result<(), AgentError> {
+        let root_key = der_encode_bls_key(root_key)?;
         if let Ok(mut write_guard) = self.root_key.write() {
-            *write_guard = Some(root_key);
+            *write_guard = root_key;
         }
+        // Delegations were verified against the previous root key.
+        self.delegation_cache.clear();
         Ok(())
     }
 
     fn read_root_key(&self) -> Result<Vec<u8>, AgentError> {
         if let Ok(read_lock) = self.root_key.read() {
-            if let Some(root_key) = read_lock.clone() {
-                Ok(root_key)
-            } else {
-                Err(AgentError::CouldNotReadRootKey())
-            }
+            Ok(read_lock.clone())
         } else {
             Err(AgentError::CouldNotReadRootKey())
         }
@@ -245,28 +293,40 @@ impl Agent {
         buf
     }
 
-    async fn query_endpoint<A>(
+    /// Sign a request, and return its request id along with its CBOR-encoded envelope.
+    async fn sign_envelope<T: Serialize>(
         &self,
-        effective_canister_id: Principal,
-        request: QueryContent,
-    ) -> Result<A, AgentError>
-    where
-        A: serde::de::DeserializeOwned,
-    {
+        identity: &dyn AsyncIdentity,
+        request: T,
+    ) -> Result<(RequestId, Vec<u8>), AgentError> {
         let request_id = to_request_id(&request)?;
         let msg = self.construct_message(&request_id);
-        let signature = self.identity.sign(&msg).map_err(AgentError::SigningError)?;
+        let signature = identity.sign(&msg).await?;
 
         let envelope = Envelope {
             content: request,
             sender_pubkey: signature.public_key,
             sender_sig: signature.signature,
+            sender_delegation: signature.delegations,
         };
 
         let mut serialized_bytes = Vec::new();
         let mut serializer = serde_cbor::Serializer::new(&mut serialized_bytes);
         serializer.self_describe()?;
         envelope.serialize(&mut serializer)?;
+        Ok((request_id, serialized_bytes))
+    }
+
+    async fn query_endpoint<A>(
+        &self,
+        identity: &dyn AsyncIdentity,
+        effective_canister_id: Principal,
+        request: QueryContent,
+    ) -> Result<A, AgentError>
+    where
+        A: serde::de::DeserializeOwned,
+    {
+        let (_, serialized_bytes) = self.sign_envelope(identity, request).await?;
 
         let bytes = self
             .transport
@@ -277,26 +337,14 @@ impl Agent {
 
     async fn read_state_endpoint<A>(
         &self,
+        identity: &dyn AsyncIdentity,
         effective_canister_id: Principal,
         request: ReadStateContent,
     ) -> Result<A, AgentError>
     where
         A: serde::de::DeserializeOwned,
     {
-        let request_id = to_request_id(&request)?;
-        let msg = self.construct_message(&request_id);
-        let signature = self.identity.sign(&msg).map_err(AgentError::SigningError)?;
-
-        let envelope = Envelope {
-            content: request,
-            sender_pubkey: signature.public_key,
-            sender_sig: signature.signature,
-        };
-
-        let mut serialized_bytes = Vec::new();
-        let mut serializer = serde_cbor::Serializer::new(&mut serialized_bytes);
-        serializer.self_describe()?;
-        envelope.serialize(&mut serializer)?;
+        let (_, serialized_bytes) = self.sign_envelope(identity, request).await?;
 
         let bytes = self
             .transport
@@ -307,23 +355,11 @@ impl Agent {
 
     async fn call_endpoint(
         &self,
+        identity: &dyn AsyncIdentity,
         effective_canister_id: Principal,
         request: CallRequestContent,
     ) -> Result<RequestId, AgentError> {
-        let request_id = to_request_id(&request)?;
-        let msg = self.construct_message(&request_id);
-        let signature = self.identity.sign(&msg).map_err(AgentError::SigningError)?;
-
-        let envelope = Envelope {
-            content: request,
-            sender_pubkey: signature.public_key,
-            sender_sig: signature.signature,
-        };
-
-        let mut serialized_bytes = Vec::new();
-        let mut serializer = serde_cbor::Serializer::new(&mut serialized_bytes);
-        serializer.self_describe()?;
-        envelope.serialize(&mut serializer)?;
+        let (request_id, serialized_bytes) = self.sign_envelope(identity, request).await?;
 
         self.transport
             .call(effective_canister_id, serialized_bytes, request_id)
@@ -335,6 +371,7 @@ impl Agent {
     /// The encoding is left as an exercise to the user.
     async fn query_raw(
         &self,
+        identity: &dyn AsyncIdentity,
         canister_id: &Principal,
         effective_canister_id: Principal,
         method_name: &str,
@@ -342,9 +379,10 @@ impl Agent {
         ingress_expiry_datetime: Option<u64>,
     ) -> Result<Vec<u8>, AgentError> {
         self.query_endpoint::<replica_api::QueryResponse>(
+            identity,
             effective_canister_id,
             QueryContent::QueryRequest {
-                sender: self.identity.sender().map_err(AgentError::SigningError)?,
+                sender: identity.sender()?,
                 canister_id: canister_id.clone(),
                 method_name: method_name.to_string(),
                 arg: arg.to_vec(),
@@ -352,22 +390,14 @@ impl Agent {
             },
         )
         .await
-        .and_then(|response| match response {
-            replica_api::QueryResponse::Replied { reply } => Ok(reply.arg),
-            replica_api::QueryResponse::Rejected {
-                reject_code,
-                reject_message,
-            } => Err(AgentError::ReplicaError {
-                reject_code,
-                reject_message,
-            }),
-        })
+        .and_then(query_reply)
     }
 
     /// The simplest way to do an update call; sends a byte array and will return a RequestId.
     /// The RequestId should then be used for request_status (most likely in a loop).
     async fn update_raw(
         &self,
+        identity: &dyn AsyncIdentity,
         canister_id: &Principal,
         effective_canister_id: Principal,
         method_name: &str,
@@ -375,42 +405,96 @@ impl Agent {
         ingress_expiry_datetime: Option<u64>,
     ) -> Result<RequestId, AgentError> {
         self.call_endpoint(
+            identity,
             effective_canister_id,
             CallRequestContent::CallRequest {
                 canister_id: canister_id.clone(),
                 method_name: method_name.into(),
                 arg: arg.to_vec(),
                 nonce: self.nonce_factory.generate().map(|b| b.as_slice().into()),
-                sender: self.identity.sender().map_err(AgentError::SigningError)?,
+                sender: identity.sender()?,
                 ingress_expiry: ingress_expiry_datetime.unwrap_or_else(|| self.get_expiry_date()),
             },
         )
         .await
     }
 
-    async fn read_state_raw(
+    /// Request the certified state at the given paths, and verify the returned certificate
+    /// against the root key of the agent. Paths are lists of labels, for example
+    /// `["time"]` or `["subnet", <subnet id>, "public_key"]`.
+    pub async fn read_state(
+        &self,
+        paths: Vec<Vec<Label>>,
+        effective_canister_id: Principal,
+    ) -> Result<Certificate, AgentError> {
+        self.read_state_as(&*self.identity, paths, effective_canister_id)
+            .await
+    }
+
+    /// Same as [Agent::read_state], but signs the request with `identity`.
+    async fn read_state_as(
         &self,
+        identity: &dyn AsyncIdentity,
         paths: Vec<Vec<Label>>,
         effective_canister_id: Principal,
     ) -> Result<Certificate, AgentError> {
         let read_state_response: ReadStateResponse = self
             .read_state_endpoint(
+                identity,
                 effective_canister_id,
                 ReadStateContent::ReadStateRequest {
-                    sender: self.identity.sender().map_err(AgentError::SigningError)?,
+                    sender: identity.sender()?,
                     paths,
                     ingress_expiry: self.get_expiry_date(),
                 },
             )
             .await?;
 
+        self.verify_read_state_response(read_state_response, &effective_canister_id)
+    }
+
+    /// Decode the certificate of a read_state response, and verify it.
+    fn verify_read_state_response(
+        &self,
+        read_state_response: ReadStateResponse,
+        effective_canister_id: &Principal,
+    ) -> Result<Certificate, AgentError> {
         let cert: Certificate = serde_cbor::from_slice(&read_state_response.certificate)
             .map_err(AgentError::InvalidCborData)?;
-        self.verify(&cert)?;
+        self.verify(&cert, effective_canister_id)?;
+        self.check_certificate_time(&cert)?;
         Ok(cert)
     }
 
-    fn verify(&self, cert: &Certificate) -> Result<(), AgentError> {
+    /// Verify that the time certified by a certificate is neither older than the maximum
+    /// certificate age, nor in the future (beyond a permitted clock drift).
+    fn check_certificate_time(&self, cert: &Certificate) -> Result<(), AgentError> {
+        let certificate_time = lookup_time(cert)?;
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .expect("Time wrapped around.");
+        let local_time = now.as_nanos() as u64;
+
+        let oldest = now
+            .checked_sub(self.max_certificate_age)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        let newest = (now + CERTIFICATE_PERMITTED_DRIFT).as_nanos() as u64;
+        if certificate_time < oldest || certificate_time > newest {
+            Err(AgentError::CertificateNotFresh {
+                certificate_time,
+                local_time,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    fn verify(
+        &self,
+        cert: &Certificate,
+        effective_canister_id: &Principal,
+    ) -> Result<(), AgentError> {
         let sig = &cert.signature;
 
         let root_hash = cert.tree.digest();
@@ -418,10 +502,16 @@ impl Agent {
         msg.extend_from_slice(IC_STATE_ROOT_DOMAIN_SEPARATOR);
         msg.extend_from_slice(&root_hash);
 
-        let der_key = self.check_delegation(&cert.delegation)?;
-        let key = extract_der(der_key)?;
-
-        let result = bls::core_verify(sig, &*msg, &*key);
+        let result = match &cert.delegation {
+            None => {
+                let key = extract_der(self.read_root_key()?)?;
+                bls::core_verify(sig, &*msg, &*key)
+            }
+            Some(delegation) => {
+                let subnet_key = self.check_delegation(delegation, effective_canister_id)?;
+                bls::core_verify_prepared(sig, &*msg, &subnet_key.public_key)
+            }
+        };
         if result != bls::BLS_OK {
             Err(AgentError::CertificateVerificationFailed())
         } else {
@@ -429,21 +519,61 @@ impl Agent {
         }
     }
 
-    fn check_delegation(&self, delegation: &Option<Delegation>) -> Result<Vec<u8>, AgentError> {
-        match delegation {
-            None => self.read_root_key(),
-            Some(delegation) => {
+    /// Verify a delegation to a subnet, and that the subnet is authoritative for the
+    /// effective canister id. Verified delegations are cached for the maximum certificate
+    /// age, counted from when they were verified: the delegation certificate is signed when
+    /// the subnet is set up, and may be much older than the certificates it signs.
+    fn check_delegation(
+        &self,
+        delegation: &Delegation,
+        effective_canister_id: &Principal,
+    ) -> Result<Arc<SubnetKey>, AgentError> {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .expect("Time wrapped around.")
+            .as_nanos() as u64;
+
+        let subnet_key = match self.delegation_cache.get(&delegation.subnet_id, now) {
+            Some(subnet_key) => subnet_key,
+            None => {
                 let cert: Certificate = serde_cbor::from_slice(&delegation.certificate)
                     .map_err(AgentError::InvalidCborData)?;
-                self.verify(&cert)?;
+                // Only the root key can delegate to a subnet.
+                if cert.delegation.is_some() {
+                    return Err(AgentError::CertificateHasTooManyDelegations());
+                }
+                self.verify(&cert, effective_canister_id)?;
+
                 let public_key_path = vec![
                     "subnet".into(),
                     delegation.subnet_id.clone().into(),
                     "public_key".into(),
                 ];
-                lookup_value(&cert, public_key_path).map(|pk| pk.to_vec())
+                let der_key = lookup_value(&cert, public_key_path)?.to_vec();
+                let public_key = bls::prepare_public_key(&extract_der(der_key)?)
+                    .ok_or_else(AgentError::CertificateVerificationFailed)?;
+                let max_age =
+                    u64::try_from(self.max_certificate_age.as_nanos()).unwrap_or(u64::MAX);
+                let expiry = now.saturating_add(max_age);
+
+                self.delegation_cache.insert(
+                    delegation.subnet_id.clone(),
+                    SubnetKey {
+                        public_key,
+                        canister_ranges: lookup_canister_ranges(&cert, &delegation.subnet_id)?,
+                        expiry,
+                    },
+                )
             }
+        };
+
+        if !principal_is_within_ranges(effective_canister_id, &subnet_key.canister_ranges) {
+            return Err(AgentError::CertificateNotAuthorized {
+                subnet_id: delegation.subnet_id.clone(),
+                canister_id: effective_canister_id.clone(),
+            });
         }
+        Ok(subnet_key)
     }
 
     pub async fn read_state_canister_info(
@@ -457,7 +587,7 @@ impl Agent {
             path.into(),
         ]];
 
-        let cert = self.read_state_raw(paths, canister_id.clone()).await?;
+        let cert = self.read_state(paths, canister_id.clone()).await?;
 
         lookup_canister_info(cert, canister_id, path)
     }
@@ -467,12 +597,127 @@ impl Agent {
         request_id: &RequestId,
         effective_canister_id: Principal,
     ) -> Result<RequestStatusResponse, AgentError> {
+        let cert = self
+            .read_request_status(&*self.identity, request_id, effective_canister_id)
+            .await?;
+
+        lookup_request_status(cert, request_id)
+    }
+
+    /// Read the status of a request. Only the sender of a request can read its status, so
+    /// `identity` must be the identity that signed it.
+    async fn read_request_status(
+        &self,
+        identity: &dyn AsyncIdentity,
+        request_id: &RequestId,
+        effective_canister_id: Principal,
+    ) -> Result<Certificate, AgentError> {
         let paths: Vec<Vec<Label>> =
             vec![vec!["request_status".into(), request_id.to_vec().into()]];
 
-        let cert = self.read_state_raw(paths, effective_canister_id).await?;
+        self.read_state_as(identity, paths, effective_canister_id)
+            .await
+    }
 
-        lookup_request_status(cert, request_id)
+    /// Sign a request for the status of the update call `request_id`, to be submitted
+    /// later with [Agent::submit_request_status]. The request is signed by the identity of
+    /// the agent, which must be the sender of the update call. It expires at
+    /// `ingress_expiry_datetime` (in nanoseconds since the epoch), or after the default
+    /// ingress expiry of the agent if [None].
+    pub async fn sign_request_status(
+        &self,
+        request_id: RequestId,
+        effective_canister_id: Principal,
+        ingress_expiry_datetime: Option<u64>,
+    ) -> Result<SignedRequestStatus, AgentError> {
+        self.sign_request_status_as(
+            &*self.identity,
+            request_id,
+            effective_canister_id,
+            ingress_expiry_datetime,
+        )
+        .await
+    }
+
+    /// Same as [Agent::sign_request_status], but signs the request with `identity`, for
+    /// update calls signed with another identity than the one of the agent.
+    pub async fn sign_request_status_as(
+        &self,
+        identity: &dyn AsyncIdentity,
+        request_id: RequestId,
+        effective_canister_id: Principal,
+        ingress_expiry_datetime: Option<u64>,
+    ) -> Result<SignedRequestStatus, AgentError> {
+        let ingress_expiry = ingress_expiry_datetime.unwrap_or_else(|| self.get_expiry_date());
+        let sender = identity.sender()?;
+        let paths: Vec<Vec<Label>> =
+            vec![vec!["request_status".into(), request_id.to_vec().into()]];
+        let request = ReadStateContent::ReadStateRequest {
+            ingress_expiry,
+            sender: sender.clone(),
+            paths,
+        };
+        let (_, signed_request_status) = self.sign_envelope(identity, request).await?;
+        Ok(SignedRequestStatus {
+            ingress_expiry,
+            sender,
+            effective_canister_id,
+            request_id,
+            signed_request_status,
+        })
+    }
+
+    /// Submit an update call signed with [UpdateBuilder::sign], and return its request id.
+    /// Its status can then be read with a request signed with
+    /// [Agent::sign_request_status].
+    pub async fn submit_update(
+        &self,
+        signed_update: &SignedUpdate,
+    ) -> Result<RequestId, AgentError> {
+        self.transport
+            .call(
+                signed_update.effective_canister_id.clone(),
+                signed_update.signed_update.clone(),
+                signed_update.request_id,
+            )
+            .await?;
+        Ok(signed_update.request_id)
+    }
+
+    /// Submit a query call signed with [QueryBuilder::sign], and return its reply.
+    pub async fn submit_query(&self, signed_query: &SignedQuery) -> Result<Vec<u8>, AgentError> {
+        let bytes = self
+            .transport
+            .query(
+                signed_query.effective_canister_id.clone(),
+                signed_query.signed_query.clone(),
+            )
+            .await?;
+        serde_cbor::from_slice(&bytes)
+            .map_err(AgentError::InvalidCborData)
+            .and_then(query_reply)
+    }
+
+    /// Submit a request status signed with [Agent::sign_request_status], verify the
+    /// certificate returned, and return the status of the update call.
+    pub async fn submit_request_status(
+        &self,
+        signed_request_status: &SignedRequestStatus,
+    ) -> Result<RequestStatusResponse, AgentError> {
+        let bytes = self
+            .transport
+            .read_state(
+                signed_request_status.effective_canister_id.clone(),
+                signed_request_status.signed_request_status.clone(),
+            )
+            .await?;
+        let read_state_response: ReadStateResponse =
+            serde_cbor::from_slice(&bytes).map_err(AgentError::InvalidCborData)?;
+        let cert = self.verify_read_state_response(
+            read_state_response,
+            &signed_request_status.effective_canister_id,
+        )?;
+        lookup_request_status(cert, &signed_request_status.request_id)
     }
 
     /// Returns an UpdateBuilder enabling the construction of an update call without
@@ -502,6 +747,29 @@ impl Agent {
     }
 }
 
+/// The reply of a query, or the error it was rejected with.
+fn query_reply(response: replica_api::QueryResponse) -> Result<Vec<u8>, AgentError> {
+    match response {
+        replica_api::QueryResponse::Replied { reply } => Ok(reply.arg),
+        replica_api::QueryResponse::Rejected {
+            reject_code,
+            reject_message,
+        } => Err(AgentError::ReplicaError {
+            reject_code,
+            reject_message,
+        }),
+    }
+}
+
+/// Whether a principal lies within one of the (inclusive) ranges of a subnet. Principals are
+/// compared by their binary representation.
+fn principal_is_within_ranges(principal: &Principal, ranges: &[(Vec<u8>, Vec<u8>)]) -> bool {
+    let principal = principal.as_slice();
+    ranges
+        .iter()
+        .any(|(low, high)| low.as_slice() <= principal && principal <= high.as_slice())
+}
+
 /// A Query Request Builder.
 ///
 /// This makes it easier to do query calls without actually passing all arguments.
@@ -512,6 +780,7 @@ pub struct QueryBuilder<'agent> {
     method_name: String,
     arg: Vec<u8>,
     ingress_expiry_datetime: Option<u64>,
+    identity: Arc<dyn AsyncIdentity>,
 }
 
 impl<'agent> QueryBuilder<'agent> {
@@ -523,9 +792,17 @@ impl<'agent> QueryBuilder<'agent> {
             method_name,
             arg: vec![],
             ingress_expiry_datetime: None,
+            identity: agent.identity.clone(),
         }
     }
 
+    /// Sign this call with `identity` instead of the identity of the agent. The transport,
+    /// root key and caches of the agent are still used.
+    pub fn with_identity(&mut self, identity: Arc<dyn AsyncIdentity>) -> &mut Self {
+        self.identity = identity;
+        self
+    }
+
     pub fn with_effective_canister_id(&mut self, canister_id: Principal) -> &mut Self {
         self.effective_canister_id = canister_id;
         self
@@ -565,10 +842,38 @@ impl<'agent> QueryBuilder<'agent> {
         self
     }
 
+    /// Sign the query call without sending it, so it can be submitted later with
+    /// [Agent::submit_query].
+    pub async fn sign(&self) -> Result<SignedQuery, AgentError> {
+        let ingress_expiry = self
+            .ingress_expiry_datetime
+            .unwrap_or_else(|| self.agent.get_expiry_date());
+        let sender = self.identity.sender()?;
+        let request = QueryContent::QueryRequest {
+            ingress_expiry,
+            sender: sender.clone(),
+            canister_id: self.canister_id.clone(),
+            method_name: self.method_name.clone(),
+            arg: self.arg.clone(),
+        };
+        let (request_id, signed_query) = self.agent.sign_envelope(&*self.identity, request).await?;
+        Ok(SignedQuery {
+            ingress_expiry,
+            sender,
+            canister_id: self.canister_id.clone(),
+            method_name: self.method_name.clone(),
+            arg: self.arg.clone(),
+            effective_canister_id: self.effective_canister_id.clone(),
+            request_id,
+            signed_query,
+        })
+    }
+
     /// Make a query call. This will return a byte vector.
     pub async fn call(&self) -> Result<Vec<u8>, AgentError> {
         self.agent
             .query_raw(
+                &*self.identity,
                 &self.canister_id,
                 self.effective_canister_id.clone(),
                 self.method_name.as_str(),
@@ -590,6 +895,7 @@ pub struct UpdateBuilder<'agent> {
     pub method_name: String,
     pub arg: Vec<u8>,
     pub ingress_expiry_datetime: Option<u64>,
+    identity: Arc<dyn AsyncIdentity>,
 }
 
 impl<'agent> UpdateBuilder<'agent> {
@@ -601,9 +907,17 @@ impl<'agent> UpdateBuilder<'agent> {
             method_name,
             arg: vec![],
             ingress_expiry_datetime: None,
+            identity: agent.identity.clone(),
         }
     }
 
+    /// Sign this call with `identity` instead of the identity of the agent. The transport,
+    /// root key and caches of the agent are still used.
+    pub fn with_identity(&mut self, identity: Arc<dyn AsyncIdentity>) -> &mut Self {
+        self.identity = identity;
+        self
+    }
+
     pub fn with_effective_canister_id(&mut self, canister_id: Principal) -> &mut Self {
         self.effective_canister_id = canister_id;
         self
@@ -644,26 +958,43 @@ impl<'agent> UpdateBuilder<'agent> {
     }
 
     /// Make an update call. This will call request_status on the RequestId in a loop and return
-    /// the response as a byte vector.
-    pub async fn call_and_wait<W: Waiter>(&self, mut waiter: W) -> Result<Vec<u8>, AgentError> {
+    /// the response as a byte vector. The `waiter` decides how long to wait between polls,
+    /// and when to give up.
+    ///
+    /// If the replica certifies a time past the ingress expiry of the request before it
+    /// accepted the request, this returns [AgentError::RequestExpired]: the request did not
+    /// execute, and will never execute, so it is safe to submit it again.
+    pub async fn call_and_wait<W: PollingStrategy>(
+        &self,
+        mut waiter: W,
+    ) -> Result<Vec<u8>, AgentError> {
+        let ingress_expiry = self
+            .ingress_expiry_datetime
+            .unwrap_or_else(|| self.agent.get_expiry_date());
         let request_id = self
             .agent
             .update_raw(
+                &*self.identity,
                 &self.canister_id,
                 self.effective_canister_id.clone(),
                 self.method_name.as_str(),
                 self.arg.as_slice(),
-                self.ingress_expiry_datetime,
+                Some(ingress_expiry),
             )
             .await?;
         waiter.start();
         let mut request_accepted = false;
         loop {
-            match self
+            let cert = self
                 .agent
-                .request_status_raw(&request_id, self.effective_canister_id.clone())
-                .await?
-            {
+                .read_request_status(
+                    &*self.identity,
+                    &request_id,
+                    self.effective_canister_id.clone(),
+                )
+                .await?;
+            let certified_time = lookup_time(&cert)?;
+            match lookup_request_status(cert, &request_id)? {
                 RequestStatusResponse::Replied {
                     reply: Replied::CallReplied(arg),
                 } => return Ok(arg),
@@ -676,7 +1007,16 @@ impl<'agent> UpdateBuilder<'agent> {
                         reject_message,
                     })
                 }
-                RequestStatusResponse::Unknown => (),
+                RequestStatusResponse::Unknown => {
+                    // Once the replica is past the ingress expiry, it will not accept the
+                    // request anymore.
+                    if !request_accepted && certified_time > ingress_expiry {
+                        return Err(AgentError::RequestExpired {
+                            request_id: String::from(request_id),
+                            ingress_expiry,
+                        });
+                    }
+                }
                 RequestStatusResponse::Received | RequestStatusResponse::Processing => {
                     // The system will return Unknown until the request is accepted
                     // and we generally cannot know how long that will take.
@@ -684,9 +1024,7 @@ impl<'agent> UpdateBuilder<'agent> {
                     // instantaneous. Therefore, once we know the request is accepted,
                     // we restart the waiter so the request does not time out.
                     if !request_accepted {
-                        waiter
-                            .restart()
-                            .map_err(|_| AgentError::WaiterRestartError())?;
+                        waiter.restart()?;
                         request_accepted = true;
                     }
                 }
@@ -697,17 +1035,133 @@ impl<'agent> UpdateBuilder<'agent> {
                 }
             };
 
-            waiter
-                .wait()
-                .map_err(|_| AgentError::TimeoutWaitingForResponse())?;
+            waiter.wait().await?;
         }
     }
 
+    /// Make an update call and wait for its response, like [UpdateBuilder::call_and_wait],
+    /// but without ever executing the call twice.
+    ///
+    /// The call is signed once. When submitting it or reading its status fails with a
+    /// retryable error (see [AgentError::is_retryable]), the agent cannot tell whether
+    /// the replica received it, so it reads its status again after waiting, and submits the
+    /// exact same envelope again as long as the replica does not know the request. The
+    /// replica executes a request id at most once, so the call executes at most once within
+    /// its ingress expiry; past the expiry, this returns [AgentError::RequestExpired].
+    pub async fn call_and_wait_resubmitting<W: PollingStrategy>(
+        &self,
+        mut waiter: W,
+    ) -> Result<Vec<u8>, AgentError> {
+        let signed_update = self.sign().await?;
+        let request_id = signed_update.request_id;
+        let ingress_expiry = signed_update.ingress_expiry;
+        waiter.start();
+        let mut submit = true;
+        let mut request_accepted = false;
+        loop {
+            if submit {
+                match self.agent.submit_update(&signed_update).await {
+                    Ok(_) => submit = false,
+                    Err(e) if !e.is_retryable() => return Err(e),
+                    Err(_) => {}
+                }
+            }
+
+            let cert = match self
+                .agent
+                .read_request_status(
+                    &*self.identity,
+                    &request_id,
+                    self.effective_canister_id.clone(),
+                )
+                .await
+            {
+                Ok(cert) => Some(cert),
+                Err(e) if !e.is_retryable() => return Err(e),
+                Err(_) => None,
+            };
+
+            if let Some(cert) = cert {
+                let certified_time = lookup_time(&cert)?;
+                match lookup_request_status(cert, &request_id)? {
+                    RequestStatusResponse::Replied {
+                        reply: Replied::CallReplied(arg),
+                    } => return Ok(arg),
+                    RequestStatusResponse::Rejected {
+                        reject_code,
+                        reject_message,
+                    } => {
+                        return Err(AgentError::ReplicaError {
+                            reject_code,
+                            reject_message,
+                        })
+                    }
+                    RequestStatusResponse::Unknown => {
+                        if !request_accepted && certified_time > ingress_expiry {
+                            return Err(AgentError::RequestExpired {
+                                request_id: String::from(request_id),
+                                ingress_expiry,
+                            });
+                        }
+                    }
+                    RequestStatusResponse::Received | RequestStatusResponse::Processing => {
+                        if !request_accepted {
+                            waiter.restart()?;
+                            request_accepted = true;
+                        }
+                        submit = false;
+                    }
+                    RequestStatusResponse::Done => {
+                        return Err(AgentError::RequestStatusDoneNoReply(String::from(
+                            request_id,
+                        )))
+                    }
+                };
+            }
+
+            waiter.wait().await?;
+        }
+    }
+
+    /// Sign the update call without sending it, so it can be submitted later with
+    /// [Agent::submit_update].
+    pub async fn sign(&self) -> Result<SignedUpdate, AgentError> {
+        let ingress_expiry = self
+            .ingress_expiry_datetime
+            .unwrap_or_else(|| self.agent.get_expiry_date());
+        let sender = self.identity.sender()?;
+        let request = CallRequestContent::CallRequest {
+            nonce: self
+                .agent
+                .nonce_factory
+                .generate()
+                .map(|b| b.as_slice().into()),
+            ingress_expiry,
+            sender: sender.clone(),
+            canister_id: self.canister_id.clone(),
+            method_name: self.method_name.clone(),
+            arg: self.arg.clone(),
+        };
+        let (request_id, signed_update) =
+            self.agent.sign_envelope(&*self.identity, request).await?;
+        Ok(SignedUpdate {
+            ingress_expiry,
+            sender,
+            canister_id: self.canister_id.clone(),
+            method_name: self.method_name.clone(),
+            arg: self.arg.clone(),
+            effective_canister_id: self.effective_canister_id.clone(),
+            request_id,
+            signed_update,
+        })
+    }
+
     /// Make an update call. This will return a RequestId.
     /// The RequestId should then be used for request_status (most likely in a loop).
     pub async fn call(&self) -> Result<RequestId, AgentError> {
         self.agent
             .update_raw(
+                &*self.identity,
                 &self.canister_id,
                 self.effective_canister_id.clone(),
                 self.method_name.as_str(),
@@ -0,0 +1,264 @@
+//! Strategies to wait between polls of the status of a request.
+//!
+//! Unlike [delay::Waiter], a [PollingStrategy] waits asynchronously, using a timer that does
+//! not depend on any particular runtime, so it never blocks the thread driving the future.
+use crate::AgentError;
+use rand::Rng;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+/// A strategy deciding how long to wait between two polls of the status of a request, and
+/// when to give up.
+pub trait PollingStrategy: Send {
+    /// Start the strategy. This is called once, right before the status of the request is
+    /// polled for the first time.
+    fn start(&mut self);
+
+    /// Restart the strategy. This is called once the replica accepted the request, so the
+    /// time spent waiting for it to be accepted does not count towards a timeout.
+    fn restart(&mut self) -> Result<(), AgentError>;
+
+    /// Wait until the status of the request should be polled again. Returns
+    /// [AgentError::TimeoutWaitingForResponse] once the strategy gives up.
+    fn wait<'a>(&'a mut self) -> Pin<Box<dyn Future<Output = Result<(), AgentError>> + Send + 'a>>;
+}
+
+/// A [PollingStrategy] waiting longer and longer between polls, optionally with some random
+/// jitter and a maximum total duration.
+///
+/// ```
+/// use ic_agent::agent::polling::Backoff;
+/// use std::time::Duration;
+///
+/// let backoff = Backoff::exponential(Duration::from_millis(500), 1.4)
+///     .with_max_delay(Duration::from_secs(10))
+///     .with_jitter(0.1)
+///     .with_timeout(Duration::from_secs(60 * 5));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial_delay: Duration,
+    multiplier: f64,
+    max_delay: Option<Duration>,
+    jitter: f64,
+    timeout: Option<Duration>,
+
+    next_delay: Duration,
+    started: Option<Instant>,
+}
+
+impl Backoff {
+    /// Wait the same delay between every poll.
+    pub fn throttle(delay: Duration) -> Self {
+        Self::exponential(delay, 1.0)
+    }
+
+    /// Wait `initial_delay` before the second poll, then multiply the delay by `multiplier`
+    /// after every poll. The multiplier is at least 1, so delays never shrink; a NaN
+    /// multiplier keeps the delay constant.
+    pub fn exponential(initial_delay: Duration, multiplier: f64) -> Self {
+        Self {
+            initial_delay,
+            // `f64::max` returns the other argument for NaN.
+            multiplier: multiplier.max(1.0),
+            max_delay: None,
+            jitter: 0.0,
+            timeout: None,
+            next_delay: initial_delay,
+            started: None,
+        }
+    }
+
+    /// Never wait longer than `max_delay` between two polls.
+    pub fn with_max_delay(self, max_delay: Duration) -> Self {
+        Self {
+            max_delay: Some(max_delay),
+            ..self
+        }
+    }
+
+    /// Randomly shorten or lengthen every delay by up to the fraction `jitter` of it, so
+    /// many clients started at the same time do not poll in lockstep. The fraction is
+    /// clamped between 0 and 1.
+    pub fn with_jitter(self, jitter: f64) -> Self {
+        Self {
+            jitter: jitter.max(0.0).min(1.0),
+            ..self
+        }
+    }
+
+    /// Give up once `timeout` elapsed since the strategy was (re)started.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Self {
+            timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Returns how long to wait before the next poll, and advances the backoff.
+    fn next_delay(&mut self) -> Result<Duration, AgentError> {
+        let mut delay = jittered(self.next_delay, self.jitter);
+        if let (Some(started), Some(timeout)) = (self.started, self.timeout) {
+            let elapsed = started.elapsed();
+            if elapsed >= timeout {
+                return Err(AgentError::TimeoutWaitingForResponse());
+            }
+            delay = delay.min(timeout - elapsed);
+        }
+
+        self.next_delay = self.next_delay.mul_f64(self.multiplier);
+        if let Some(max_delay) = self.max_delay {
+            self.next_delay = self.next_delay.min(max_delay);
+        }
+        Ok(delay)
+    }
+}
+
+/// Randomly shorten or lengthen `delay` by up to the fraction `jitter` of it.
+pub(crate) fn jittered(delay: Duration, jitter: f64) -> Duration {
+    if jitter > 0.0 {
+        delay.mul_f64(1.0 + rand::thread_rng().gen_range(-jitter, jitter))
+    } else {
+        delay
+    }
+}
+
+impl PollingStrategy for Backoff {
+    fn start(&mut self) {
+        self.started = Some(Instant::now());
+        self.next_delay = self.initial_delay;
+    }
+
+    fn restart(&mut self) -> Result<(), AgentError> {
+        if self.started.is_none() {
+            return Err(AgentError::WaiterRestartError());
+        }
+        self.start();
+        Ok(())
+    }
+
+    fn wait<'a>(&'a mut self) -> Pin<Box<dyn Future<Output = Result<(), AgentError>> + Send + 'a>> {
+        let delay = self.next_delay();
+        Box::pin(async move {
+            futures_timer::Delay::new(delay?).await;
+            Ok(())
+        })
+    }
+}
+
+/// Adapts a [delay::Waiter] into a [PollingStrategy].
+///
+/// The waiter still sleeps the calling thread, so this only exists for compatibility with
+/// code written against waiters; prefer [Backoff] in async code.
+#[derive(Debug, Clone)]
+pub struct WaiterCompat<W: delay::Waiter + Send>(W);
+
+impl<W: delay::Waiter + Send> WaiterCompat<W> {
+    /// Wrap a waiter.
+    pub fn new(waiter: W) -> Self {
+        Self(waiter)
+    }
+
+    /// Returns the wrapped waiter.
+    pub fn into_inner(self) -> W {
+        self.0
+    }
+}
+
+impl<W: delay::Waiter + Send> From<W> for WaiterCompat<W> {
+    fn from(waiter: W) -> Self {
+        Self::new(waiter)
+    }
+}
+
+impl<W: delay::Waiter + Send> PollingStrategy for WaiterCompat<W> {
+    fn start(&mut self) {
+        self.0.start();
+    }
+
+    fn restart(&mut self) -> Result<(), AgentError> {
+        self.0
+            .restart()
+            .map_err(|_| AgentError::WaiterRestartError())
+    }
+
+    fn wait<'a>(&'a mut self) -> Pin<Box<dyn Future<Output = Result<(), AgentError>> + Send + 'a>> {
+        let result = self
+            .0
+            .wait()
+            .map_err(|_| AgentError::TimeoutWaitingForResponse());
+        Box::pin(async move { result })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Backoff, PollingStrategy};
+    use crate::AgentError;
+    use std::time::Duration;
+
+    #[test]
+    fn backoff_delays() {
+        let mut backoff = Backoff::exponential(Duration::from_millis(100), 2.0)
+            .with_max_delay(Duration::from_millis(300));
+        backoff.start();
+
+        let delays: Vec<_> = (0..4).map(|_| backoff.next_delay().unwrap()).collect();
+        assert_eq!(
+            delays,
+            vec![
+                Duration::from_millis(100),
+                Duration::from_millis(200),
+                Duration::from_millis(300),
+                Duration::from_millis(300),
+            ]
+        );
+
+        backoff.restart().unwrap();
+        assert_eq!(backoff.next_delay().unwrap(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn backoff_invalid_multiplier() {
+        for multiplier in &[0.5, -2.0, f64::NAN] {
+            let mut backoff = Backoff::exponential(Duration::from_millis(100), *multiplier);
+            backoff.start();
+            for _ in 0..3 {
+                assert_eq!(backoff.next_delay().unwrap(), Duration::from_millis(100));
+            }
+        }
+    }
+
+    #[test]
+    fn backoff_jitter() {
+        let mut backoff = Backoff::throttle(Duration::from_millis(100)).with_jitter(0.5);
+        backoff.start();
+        for _ in 0..100 {
+            let delay = backoff.next_delay().unwrap();
+            assert!(delay >= Duration::from_millis(50) && delay <= Duration::from_millis(150));
+        }
+    }
+
+    #[test]
+    fn backoff_timeout() {
+        let runtime = tokio::runtime::Runtime::new().expect("Unable to create a runtime");
+        let mut backoff =
+            Backoff::throttle(Duration::from_millis(20)).with_timeout(Duration::from_millis(50));
+        assert!(matches!(
+            backoff.restart(),
+            Err(AgentError::WaiterRestartError())
+        ));
+
+        backoff.start();
+        let result = runtime.block_on(async {
+            loop {
+                backoff.wait().await?;
+            }
+        });
+        assert!(matches!(
+            result,
+            Err(AgentError::TimeoutWaitingForResponse())
+        ));
+    }
+}
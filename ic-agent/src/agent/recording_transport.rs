@@ -0,0 +1,663 @@
+//! Transports recording the exchanges with a replica to a file, and replaying them later,
+//! so tests can run offline against captured traffic.
+//!
+//! Recorded requests are replayed when a request matches them, either by request id or,
+//! since the request id depends on the nonce and the ingress expiry, by canister, method
+//! and argument. Requests to read the state are matched by the paths they read. When a call
+//! was matched by its method, the request id of the recorded call replaces its own in these
+//! paths, so reading its status is answered with the recorded certificate. That certificate
+//! only holds the status of the recorded request id though, which
+//! [ReplayTransport::recorded_request_id] returns: the [Agent](super::Agent) only finds the
+//! status of calls whose request id does not change, i.e. when the identity, the nonce and
+//! the ingress expiry ([expire_at](super::UpdateBuilder::expire_at)) are fixed.
+//!
+//! Replayed certificates are as old as the recording, so the [Agent](super::Agent) should
+//! also be built to accept certificates of any age, with
+//! [with_max_certificate_age(Some(Duration::from_secs(u64::MAX)))](super::AgentBuilder::with_max_certificate_age).
+use crate::agent::agent_error::HttpErrorPayload;
+use crate::agent::replica_api::EnvelopeContent;
+use crate::agent::verify::decode_envelope;
+use crate::agent::ReplicaV2Transport;
+use crate::export::Principal;
+use crate::hash_tree::Label;
+use crate::{to_request_id, AgentError, RequestId};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::io::Write;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Mutex;
+use thiserror::Error;
+
+/// The endpoint a request was sent to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Endpoint {
+    Call,
+    Query,
+    ReadState,
+    Status,
+}
+
+/// The response of a replica to a recorded request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordedResponse {
+    /// The body of a successful response.
+    Ok(#[serde(with = "serde_bytes")] Vec<u8>),
+    /// An HTTP error returned by the replica.
+    HttpError {
+        status: u16,
+        content_type: Option<String>,
+        #[serde(with = "serde_bytes")]
+        content: Vec<u8>,
+    },
+    /// Any other error, replayed as an [AgentError::TransportError] with the same message.
+    Error(String),
+}
+
+impl RecordedResponse {
+    fn from_result(result: &Result<Vec<u8>, AgentError>) -> Self {
+        match result {
+            Ok(body) => RecordedResponse::Ok(body.clone()),
+            Err(AgentError::HttpError(payload)) => RecordedResponse::HttpError {
+                status: payload.status,
+                content_type: payload.content_type.clone(),
+                content: payload.content.clone(),
+            },
+            Err(e) => RecordedResponse::Error(e.to_string()),
+        }
+    }
+
+    fn into_result(self) -> Result<Vec<u8>, AgentError> {
+        match self {
+            RecordedResponse::Ok(body) => Ok(body),
+            RecordedResponse::HttpError {
+                status,
+                content_type,
+                content,
+            } => Err(AgentError::HttpError(HttpErrorPayload {
+                status,
+                content_type,
+                content,
+            })),
+            RecordedResponse::Error(message) => Err(AgentError::TransportError(message.into())),
+        }
+    }
+}
+
+/// A request sent to a replica, and its response.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Exchange {
+    /// The endpoint the request was sent to.
+    pub endpoint: Endpoint,
+    /// The effective canister id of the request, unless it was sent to the status endpoint.
+    pub effective_canister_id: Option<Principal>,
+    /// The CBOR-encoded envelope of the request, empty for the status endpoint.
+    #[serde(with = "serde_bytes")]
+    pub request: Vec<u8>,
+    /// The response of the replica.
+    pub response: RecordedResponse,
+}
+
+/// Read the exchanges written by a [RecordingTransport], a sequence of CBOR values.
+pub fn read_exchanges(bytes: &[u8]) -> Result<Vec<Exchange>, serde_cbor::Error> {
+    serde_cbor::Deserializer::from_slice(bytes)
+        .into_iter()
+        .collect()
+}
+
+/// A [ReplicaV2Transport] forwarding requests to another transport, and writing every
+/// exchange to a file, to be replayed by a [ReplayTransport].
+pub struct RecordingTransport<T> {
+    inner: T,
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl<T: ReplicaV2Transport> RecordingTransport<T> {
+    /// Record the exchanges with `inner` to the file at `path`, which is truncated.
+    pub fn create<P: AsRef<Path>>(inner: T, path: P) -> std::io::Result<Self> {
+        let file = std::fs::File::create(path)?;
+        Ok(Self::new(inner, file))
+    }
+
+    /// Record the exchanges with `inner` to `writer`.
+    pub fn new<W: 'static + Write + Send>(inner: T, writer: W) -> Self {
+        Self {
+            inner,
+            writer: Mutex::new(Box::new(writer)),
+        }
+    }
+
+    fn record(
+        &self,
+        endpoint: Endpoint,
+        effective_canister_id: Option<Principal>,
+        request: Vec<u8>,
+        result: &Result<Vec<u8>, AgentError>,
+    ) -> Result<(), AgentError> {
+        let exchange = Exchange {
+            endpoint,
+            effective_canister_id,
+            request,
+            response: RecordedResponse::from_result(result),
+        };
+        let mut writer = self.writer.lock().unwrap();
+        serde_cbor::to_writer(&mut *writer, &exchange)
+            .map_err(|e| AgentError::TransportError(Box::new(e)))?;
+        writer
+            .flush()
+            .map_err(|e| AgentError::TransportError(Box::new(e)))
+    }
+}
+
+impl<T: ReplicaV2Transport + Sync> ReplicaV2Transport for RecordingTransport<T> {
+    fn call<'a>(
+        &'a self,
+        effective_canister_id: Principal,
+        envelope: Vec<u8>,
+        request_id: RequestId,
+    ) -> Pin<Box<dyn Future<Output = Result<(), AgentError>> + Send + 'a>> {
+        Box::pin(async move {
+            let result = self
+                .inner
+                .call(effective_canister_id.clone(), envelope.clone(), request_id)
+                .await
+                .map(|()| vec![]);
+            self.record(
+                Endpoint::Call,
+                Some(effective_canister_id),
+                envelope,
+                &result,
+            )?;
+            result.map(|_| ())
+        })
+    }
+
+    fn read_state<'a>(
+        &'a self,
+        effective_canister_id: Principal,
+        envelope: Vec<u8>,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, AgentError>> + Send + 'a>> {
+        Box::pin(async move {
+            let result = self
+                .inner
+                .read_state(effective_canister_id.clone(), envelope.clone())
+                .await;
+            self.record(
+                Endpoint::ReadState,
+                Some(effective_canister_id),
+                envelope,
+                &result,
+            )?;
+            result
+        })
+    }
+
+    fn query<'a>(
+        &'a self,
+        effective_canister_id: Principal,
+        envelope: Vec<u8>,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, AgentError>> + Send + 'a>> {
+        Box::pin(async move {
+            let result = self
+                .inner
+                .query(effective_canister_id.clone(), envelope.clone())
+                .await;
+            self.record(
+                Endpoint::Query,
+                Some(effective_canister_id),
+                envelope,
+                &result,
+            )?;
+            result
+        })
+    }
+
+    fn status<'a>(
+        &'a self,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, AgentError>> + Send + 'a>> {
+        Box::pin(async move {
+            let result = self.inner.status().await;
+            self.record(Endpoint::Status, None, vec![], &result)?;
+            result
+        })
+    }
+
+    fn is_local(&self) -> bool {
+        self.inner.is_local()
+    }
+}
+
+/// The error returned by a [ReplayTransport] for a request matching no recorded exchange.
+#[derive(Error, Debug)]
+#[error("No recorded exchange matches the {endpoint:?} request.")]
+pub struct NoRecordedExchange {
+    /// The endpoint the request was sent to.
+    pub endpoint: Endpoint,
+    /// The id of the request, if its envelope could be decoded.
+    pub request_id: Option<RequestId>,
+}
+
+/// What a request is matched on when its request id differs from the recorded ones.
+#[derive(PartialEq)]
+enum RequestKey {
+    Method {
+        canister_id: Principal,
+        method_name: String,
+        arg: Vec<u8>,
+    },
+    ReadState {
+        paths: Vec<Vec<Label>>,
+    },
+}
+
+impl RequestKey {
+    /// Replace the request ids found in `request_ids` by the ones they map to, in the paths
+    /// reading the status of requests.
+    fn with_request_ids(self, request_ids: &BTreeMap<RequestId, RequestId>) -> Self {
+        match self {
+            RequestKey::ReadState { paths } => RequestKey::ReadState {
+                paths: paths
+                    .into_iter()
+                    .map(|mut path| {
+                        if let [request_status, request_id, ..] = path.as_mut_slice() {
+                            if request_status.as_bytes() == b"request_status" {
+                                if let Some((_, recorded)) = request_ids
+                                    .iter()
+                                    .find(|(id, _)| id.as_slice() == request_id.as_bytes())
+                                {
+                                    *request_id = recorded.to_vec().into();
+                                }
+                            }
+                        }
+                        path
+                    })
+                    .collect(),
+            },
+            key => key,
+        }
+    }
+}
+
+fn request_key(envelope: &[u8]) -> (Option<RequestId>, Option<RequestKey>) {
+    let content = match decode_envelope(envelope) {
+        Ok(envelope) => envelope.content,
+        Err(_) => return (None, None),
+    };
+    let request_id = to_request_id(&content).ok();
+    let key = match content {
+        EnvelopeContent::Call {
+            canister_id,
+            method_name,
+            arg,
+            ..
+        }
+        | EnvelopeContent::Query {
+            canister_id,
+            method_name,
+            arg,
+            ..
+        } => RequestKey::Method {
+            canister_id,
+            method_name,
+            arg,
+        },
+        EnvelopeContent::ReadState { paths, .. } => RequestKey::ReadState { paths },
+    };
+    (request_id, Some(key))
+}
+
+struct RecordedExchange {
+    exchange: Exchange,
+    request_id: Option<RequestId>,
+    key: Option<RequestKey>,
+    replayed: bool,
+}
+
+/// A [ReplicaV2Transport] answering requests with the responses recorded by a
+/// [RecordingTransport], without connecting to a replica.
+///
+/// Requests matching several recorded exchanges are answered with their responses in the
+/// order they were recorded, the last one being repeated once all were replayed. Requests
+/// matching none fail with a [NoRecordedExchange] transport error.
+pub struct ReplayTransport {
+    exchanges: Mutex<Vec<RecordedExchange>>,
+    request_ids: Mutex<BTreeMap<RequestId, RequestId>>,
+}
+
+impl ReplayTransport {
+    /// Replay the exchanges recorded to the file at `path`.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let bytes = std::fs::read(path)?;
+        let exchanges = read_exchanges(&bytes)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        Ok(Self::new(exchanges))
+    }
+
+    /// Replay the given exchanges.
+    pub fn new(exchanges: Vec<Exchange>) -> Self {
+        let exchanges = exchanges
+            .into_iter()
+            .map(|exchange| {
+                let (request_id, key) = request_key(&exchange.request);
+                RecordedExchange {
+                    exchange,
+                    request_id,
+                    key,
+                    replayed: false,
+                }
+            })
+            .collect();
+        Self {
+            exchanges: Mutex::new(exchanges),
+            request_ids: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// The request id of the recorded call a call was answered with, when they differ.
+    pub fn recorded_request_id(&self, request_id: &RequestId) -> Option<RequestId> {
+        self.request_ids.lock().unwrap().get(request_id).cloned()
+    }
+
+    /// The recorded exchanges which were not replayed yet.
+    pub fn unreplayed(&self) -> Vec<Exchange> {
+        self.exchanges
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|recorded| !recorded.replayed)
+            .map(|recorded| recorded.exchange.clone())
+            .collect()
+    }
+
+    fn replay(&self, endpoint: Endpoint, envelope: &[u8]) -> Result<Vec<u8>, AgentError> {
+        let mut exchanges = self.exchanges.lock().unwrap();
+        let mut request_ids = self.request_ids.lock().unwrap();
+        let (request_id, key) = if endpoint == Endpoint::Status {
+            (None, None)
+        } else {
+            request_key(envelope)
+        };
+        let key = key.map(|key| key.with_request_ids(&request_ids));
+        let index = if endpoint == Endpoint::Status {
+            replay_matching(&mut exchanges, |recorded| {
+                recorded.exchange.endpoint == endpoint
+            })
+        } else {
+            replay_matching(&mut exchanges, |recorded| {
+                recorded.exchange.endpoint == endpoint
+                    && request_id.is_some()
+                    && recorded.request_id == request_id
+            })
+            .or_else(|| {
+                let index = replay_matching(&mut exchanges, |recorded| {
+                    recorded.exchange.endpoint == endpoint && key.is_some() && recorded.key == key
+                })?;
+                if let (Endpoint::Call, Some(request_id), Some(recorded_request_id)) =
+                    (endpoint, &request_id, &exchanges[index].request_id)
+                {
+                    request_ids.insert(*request_id, *recorded_request_id);
+                }
+                Some(index)
+            })
+        };
+        match index {
+            Some(index) => exchanges[index].exchange.response.clone().into_result(),
+            None => Err(AgentError::TransportError(Box::new(NoRecordedExchange {
+                endpoint,
+                request_id,
+            }))),
+        }
+    }
+}
+
+/// Mark as replayed the first exchange matching `predicate` which was not replayed yet, or
+/// the last one matching it, and return its index.
+fn replay_matching<F: Fn(&RecordedExchange) -> bool>(
+    exchanges: &mut [RecordedExchange],
+    predicate: F,
+) -> Option<usize> {
+    let index = exchanges
+        .iter()
+        .position(|recorded| !recorded.replayed && predicate(recorded))
+        .or_else(|| exchanges.iter().rposition(|recorded| predicate(recorded)))?;
+    exchanges[index].replayed = true;
+    Some(index)
+}
+
+impl ReplicaV2Transport for ReplayTransport {
+    fn call<'a>(
+        &'a self,
+        _effective_canister_id: Principal,
+        envelope: Vec<u8>,
+        _request_id: RequestId,
+    ) -> Pin<Box<dyn Future<Output = Result<(), AgentError>> + Send + 'a>> {
+        Box::pin(async move { self.replay(Endpoint::Call, &envelope).map(|_| ()) })
+    }
+
+    fn read_state<'a>(
+        &'a self,
+        _effective_canister_id: Principal,
+        envelope: Vec<u8>,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, AgentError>> + Send + 'a>> {
+        Box::pin(async move { self.replay(Endpoint::ReadState, &envelope) })
+    }
+
+    fn query<'a>(
+        &'a self,
+        _effective_canister_id: Principal,
+        envelope: Vec<u8>,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, AgentError>> + Send + 'a>> {
+        Box::pin(async move { self.replay(Endpoint::Query, &envelope) })
+    }
+
+    fn status<'a>(
+        &'a self,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, AgentError>> + Send + 'a>> {
+        Box::pin(async move { self.replay(Endpoint::Status, &[]) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::mock_transport::{MockReply, MockTransport};
+    use crate::agent::replica_api::{CallReply, QueryResponse};
+    use crate::agent::test_transport::{unavailable, TestTransport};
+    use crate::agent::{Backoff, Replied, RequestStatusResponse};
+    use crate::Agent;
+    use std::time::{Duration, SystemTime};
+
+    /// A transport answering queries with a reply, and failing on other requests.
+    fn replying_transport() -> TestTransport {
+        TestTransport::new(|| Err(unavailable())).with_query_answer(|| {
+            let response = QueryResponse::Replied {
+                reply: CallReply {
+                    arg: b"hello".to_vec(),
+                },
+            };
+            Ok(serde_cbor::to_vec(&response).unwrap())
+        })
+    }
+
+    async fn greet(agent: &Agent, arg: &[u8]) -> Result<Vec<u8>, AgentError> {
+        agent
+            .query(&Principal::management_canister(), "greet")
+            .with_arg(arg)
+            .call()
+            .await
+    }
+
+    #[test]
+    fn record_and_replay() -> Result<(), AgentError> {
+        let path = std::env::temp_dir().join(format!("ic-agent-record-{}", std::process::id()));
+        let runtime = tokio::runtime::Runtime::new().expect("Unable to create a runtime");
+
+        let agent = Agent::builder()
+            .with_transport(RecordingTransport::create(replying_transport(), &path).unwrap())
+            .build()?;
+        runtime.block_on(async {
+            assert_eq!(greet(&agent, b"world").await?, b"hello");
+            assert!(agent.status().await.is_err());
+            Ok::<(), AgentError>(())
+        })?;
+
+        let exchanges = read_exchanges(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(exchanges.len(), 2);
+        assert_eq!(exchanges[0].endpoint, Endpoint::Query);
+        assert_eq!(
+            exchanges[1].response,
+            RecordedResponse::HttpError {
+                status: 503,
+                content_type: None,
+                content: vec![],
+            }
+        );
+
+        // The replayed query expires at another time, so its request id differs.
+        let agent = Agent::builder()
+            .with_transport(ReplayTransport::from_file(&path).unwrap())
+            .with_ingress_expiry(Some(Duration::from_secs(60)))
+            .build()?;
+        std::fs::remove_file(&path).unwrap();
+        runtime.block_on(async {
+            assert_eq!(greet(&agent, b"world").await?, b"hello");
+            assert!(matches!(
+                agent.status().await,
+                Err(AgentError::HttpError(HttpErrorPayload { status: 503, .. }))
+            ));
+            match greet(&agent, b"nobody").await {
+                Err(AgentError::TransportError(e)) => {
+                    assert!(e.downcast_ref::<NoRecordedExchange>().is_some())
+                }
+                result => panic!("Unexpected result {:?}", result),
+            }
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn replay_in_order() {
+        let status = |body: &[u8]| Exchange {
+            endpoint: Endpoint::Status,
+            effective_canister_id: None,
+            request: vec![],
+            response: RecordedResponse::Ok(body.to_vec()),
+        };
+        let transport = ReplayTransport::new(vec![status(b"first"), status(b"second")]);
+        let runtime = tokio::runtime::Runtime::new().expect("Unable to create a runtime");
+
+        assert_eq!(runtime.block_on(transport.status()).unwrap(), b"first");
+        assert_eq!(transport.unreplayed(), vec![status(b"second")]);
+        assert_eq!(runtime.block_on(transport.status()).unwrap(), b"second");
+        assert_eq!(runtime.block_on(transport.status()).unwrap(), b"second");
+        assert!(transport.unreplayed().is_empty());
+    }
+
+    fn canister_id() -> Principal {
+        Principal::from_text("rwlgt-iiaaa-aaaaa-aaaaa-cai").unwrap()
+    }
+
+    /// A transport replying to calls, certified by a subnet.
+    fn mock_transport() -> MockTransport {
+        MockTransport::new()
+            .with_handler(&canister_id(), "greet", |arg| {
+                MockReply::Reply([b"Hello, ", arg].concat())
+            })
+            .with_subnet_delegation(
+                Principal::from_text("2vxsx-fae").unwrap(),
+                vec![(canister_id(), canister_id())],
+            )
+            .with_processing_polls(1)
+    }
+
+    fn replay_agent(path: &Path, root_key: Vec<u8>) -> Result<Agent, AgentError> {
+        Agent::builder()
+            .with_root_key(root_key)
+            .with_transport(ReplayTransport::from_file(path).unwrap())
+            .with_max_certificate_age(Some(Duration::from_secs(u64::MAX)))
+            .build()
+    }
+
+    #[test]
+    fn replay_call_and_wait() -> Result<(), AgentError> {
+        let path =
+            std::env::temp_dir().join(format!("ic-agent-record-call-{}", std::process::id()));
+        let runtime = tokio::runtime::Runtime::new().expect("Unable to create a runtime");
+        let transport = mock_transport();
+        let root_key = transport.root_key();
+        // The request ids only match if the calls expire at the same time.
+        let expiry = SystemTime::now() + Duration::from_secs(120);
+        let call_and_wait = |agent: &Agent| {
+            runtime.block_on(
+                agent
+                    .update(&canister_id(), "greet")
+                    .with_arg(b"World")
+                    .expire_at(expiry)
+                    .call_and_wait(
+                        Backoff::throttle(Duration::from_millis(5))
+                            .with_timeout(Duration::from_secs(5)),
+                    ),
+            )
+        };
+
+        let agent = Agent::builder()
+            .with_root_key(root_key.clone())
+            .with_transport(RecordingTransport::create(transport, &path).unwrap())
+            .build()?;
+        assert_eq!(call_and_wait(&agent)?, b"Hello, World");
+
+        let agent = replay_agent(&path, root_key)?;
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(call_and_wait(&agent)?, b"Hello, World");
+        Ok(())
+    }
+
+    #[test]
+    fn replay_status_of_call_with_another_request_id() -> Result<(), AgentError> {
+        let path =
+            std::env::temp_dir().join(format!("ic-agent-record-status-{}", std::process::id()));
+        let runtime = tokio::runtime::Runtime::new().expect("Unable to create a runtime");
+        let transport = mock_transport().with_processing_polls(0);
+        let root_key = transport.root_key();
+        let call = |agent: &Agent, expiry: Duration| {
+            runtime.block_on(
+                agent
+                    .update(&canister_id(), "greet")
+                    .with_arg(b"World")
+                    .expire_after(expiry)
+                    .call(),
+            )
+        };
+        let request_status = |agent: &Agent, request_id: &RequestId| {
+            runtime.block_on(agent.request_status_raw(request_id, canister_id()))
+        };
+
+        let agent = Agent::builder()
+            .with_root_key(root_key.clone())
+            .with_transport(RecordingTransport::create(transport, &path).unwrap())
+            .build()?;
+        let recorded_request_id = call(&agent, Duration::from_secs(60))?;
+        request_status(&agent, &recorded_request_id)?;
+
+        let agent = replay_agent(&path, root_key)?;
+        std::fs::remove_file(&path).unwrap();
+        let request_id = call(&agent, Duration::from_secs(120))?;
+        assert_ne!(request_id, recorded_request_id);
+
+        // The status is read with the recorded certificate, which lacks the new request id.
+        assert!(matches!(
+            request_status(&agent, &request_id),
+            Err(AgentError::LookupPathAbsent(_))
+        ));
+        assert!(matches!(
+            request_status(&agent, &recorded_request_id)?,
+            RequestStatusResponse::Replied {
+                reply: Replied::CallReplied(reply)
+            } if reply == b"Hello, World"
+        ));
+        Ok(())
+    }
+}
@@ -1,17 +1,91 @@
+use crate::agent::RejectCode;
 use crate::export::Principal;
-use crate::hash_tree::{HashTree, Label};
+use crate::hash_tree::{HashTree, Label, LookupResult};
+use crate::identity::SignedDelegation;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize)]
+/// A request as sent to the replica: its content, along with the signature of the sender.
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub struct Envelope<T: Serialize> {
+    /// The content of the request, whose request id is signed.
     pub content: T,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    /// The DER-encoded public key of the sender, absent for anonymous requests.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     #[serde(with = "serde_bytes")]
     pub sender_pubkey: Option<Vec<u8>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    /// The signature of the request id, absent for anonymous requests.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     #[serde(with = "serde_bytes")]
     pub sender_sig: Option<Vec<u8>>,
+    /// The chain of delegations from `sender_pubkey` to the key that signed the request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sender_delegation: Option<Vec<SignedDelegation>>,
+}
+
+/// The content of any request the replica accepts, as found in an [Envelope].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "request_type", rename_all = "snake_case")]
+pub enum EnvelopeContent {
+    /// An update call, submitted to `/api/v2/canister/.../call`.
+    Call {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        #[serde(with = "serde_bytes")]
+        nonce: Option<Vec<u8>>,
+        ingress_expiry: u64,
+        sender: Principal,
+        canister_id: Principal,
+        method_name: String,
+        #[serde(with = "serde_bytes")]
+        arg: Vec<u8>,
+    },
+    /// A query call, submitted to `/api/v2/canister/.../query`.
+    Query {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        #[serde(with = "serde_bytes")]
+        nonce: Option<Vec<u8>>,
+        ingress_expiry: u64,
+        sender: Principal,
+        canister_id: Principal,
+        method_name: String,
+        #[serde(with = "serde_bytes")]
+        arg: Vec<u8>,
+    },
+    /// A request to read the state tree, submitted to `/api/v2/canister/.../read_state`.
+    ReadState {
+        ingress_expiry: u64,
+        sender: Principal,
+        paths: Vec<Vec<Label>>,
+    },
+}
+
+impl EnvelopeContent {
+    /// The principal sending the request.
+    pub fn sender(&self) -> &Principal {
+        match self {
+            EnvelopeContent::Call { sender, .. }
+            | EnvelopeContent::Query { sender, .. }
+            | EnvelopeContent::ReadState { sender, .. } => sender,
+        }
+    }
+
+    /// The time (in nanoseconds since the epoch) the request expires at.
+    pub fn ingress_expiry(&self) -> u64 {
+        match self {
+            EnvelopeContent::Call { ingress_expiry, .. }
+            | EnvelopeContent::Query { ingress_expiry, .. }
+            | EnvelopeContent::ReadState { ingress_expiry, .. } => *ingress_expiry,
+        }
+    }
+
+    /// The canister called, if the request is a call or a query.
+    pub fn canister_id(&self) -> Option<&Principal> {
+        match self {
+            EnvelopeContent::Call { canister_id, .. }
+            | EnvelopeContent::Query { canister_id, .. } => Some(canister_id),
+            EnvelopeContent::ReadState { .. } => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -19,7 +93,7 @@ pub struct Envelope<T: Serialize> {
 pub enum AsyncContent {
     #[serde(rename = "call")]
     CallRequest {
-        #[serde(skip_serializing_if = "Option::is_none")]
+        #[serde(default, skip_serializing_if = "Option::is_none")]
         #[serde(with = "serde_bytes")]
         nonce: Option<Vec<u8>>,
         ingress_expiry: u64,
@@ -37,7 +111,7 @@ pub enum AsyncContent {
 pub enum CallRequestContent {
     #[serde(rename = "call")]
     CallRequest {
-        #[serde(skip_serializing_if = "Option::is_none")]
+        #[serde(default, skip_serializing_if = "Option::is_none")]
         #[serde(with = "serde_bytes")]
         nonce: Option<Vec<u8>>,
         ingress_expiry: u64,
@@ -103,21 +177,39 @@ pub struct ReadStateResponse {
 }
 
 /// A `Certificate` as defined in https://docs.dfinity.systems/public/#_certificate
-#[derive(Deserialize)]
-pub(crate) struct Certificate {
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Certificate {
+    /// The state tree certified by this certificate.
     pub tree: HashTree,
 
+    /// The BLS signature of the root hash of the tree.
     #[serde(with = "serde_bytes")]
     pub signature: Vec<u8>,
 
+    /// The delegation from the root key to the key of the subnet that signed this
+    /// certificate, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub delegation: Option<Delegation>,
 }
 
-#[derive(Deserialize)]
-pub(crate) struct Delegation {
+impl Certificate {
+    /// Lookup a path in the state tree of this certificate.
+    pub fn lookup<P>(&self, path: P) -> LookupResult
+    where
+        P: AsRef<[Label]>,
+    {
+        self.tree.lookup_path(path)
+    }
+}
+
+/// A delegation of the root key to a subnet, as part of a [Certificate].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Delegation {
+    /// The principal of the subnet the root key delegated to.
     #[serde(with = "serde_bytes")]
     pub subnet_id: Vec<u8>,
 
+    /// The CBOR-encoded certificate of the root key, containing the subnet public key.
     #[serde(with = "serde_bytes")]
     pub certificate: Vec<u8>,
 }
@@ -135,7 +227,7 @@ pub enum Status {
     Replied { reply: RequestStatusResponseReplied },
     #[serde(rename = "rejected")]
     Rejected {
-        reject_code: u64,
+        reject_code: RejectCode,
         reject_message: String,
     },
     #[serde(rename = "done")]
@@ -161,7 +253,7 @@ pub enum QueryResponse {
     Replied { reply: CallReply },
     #[serde(rename = "rejected")]
     Rejected {
-        reject_code: u64,
+        reject_code: RejectCode,
         reject_message: String,
     },
 }
@@ -1,3 +1,6 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+
 /// The response of /api/v2/canister/<effective_canister_id>/read_state with "request_status" request type.
 #[derive(Debug, Ord, PartialOrd, Eq, PartialEq)]
 pub enum RequestStatusResponse {
@@ -8,7 +11,7 @@ pub enum RequestStatusResponse {
         reply: Replied,
     },
     Rejected {
-        reject_code: u64,
+        reject_code: RejectCode,
         reject_message: String,
     },
     Done,
@@ -18,3 +21,87 @@ pub enum RequestStatusResponse {
 pub enum Replied {
     CallReplied(Vec<u8>),
 }
+
+/// The reject code of a rejected call, as defined by the Internet Computer interface
+/// specification.
+#[derive(Debug, Clone, Copy, Hash, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(from = "u64", into = "u64")]
+pub enum RejectCode {
+    /// Fatal system error, retrying is unlikely to be useful.
+    SysFatal,
+    /// Transient system error, retrying might be possible.
+    SysTransient,
+    /// The destination (canister or method) is invalid.
+    DestinationInvalid,
+    /// The canister explicitly rejected the call.
+    CanisterReject,
+    /// The canister trapped or otherwise failed.
+    CanisterError,
+    /// A reject code this version of the agent does not know about.
+    Unknown(u64),
+}
+
+impl From<u64> for RejectCode {
+    fn from(value: u64) -> Self {
+        match value {
+            1 => RejectCode::SysFatal,
+            2 => RejectCode::SysTransient,
+            3 => RejectCode::DestinationInvalid,
+            4 => RejectCode::CanisterReject,
+            5 => RejectCode::CanisterError,
+            _ => RejectCode::Unknown(value),
+        }
+    }
+}
+
+impl From<RejectCode> for u64 {
+    fn from(code: RejectCode) -> Self {
+        match code {
+            RejectCode::SysFatal => 1,
+            RejectCode::SysTransient => 2,
+            RejectCode::DestinationInvalid => 3,
+            RejectCode::CanisterReject => 4,
+            RejectCode::CanisterError => 5,
+            RejectCode::Unknown(value) => value,
+        }
+    }
+}
+
+impl Display for RejectCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RejectCode::Unknown(value) => write!(f, "{} (Unknown)", value),
+            _ => write!(f, "{} ({:?})", u64::from(*self), self),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RejectCode;
+
+    #[test]
+    fn reject_code_conversions() {
+        for code in 0..=6 {
+            assert_eq!(u64::from(RejectCode::from(code)), code);
+        }
+        assert_eq!(RejectCode::from(0), RejectCode::Unknown(0));
+        assert_eq!(RejectCode::from(6), RejectCode::Unknown(6));
+        assert_eq!(format!("{}", RejectCode::SysTransient), "2 (SysTransient)");
+        assert_eq!(format!("{}", RejectCode::Unknown(6)), "6 (Unknown)");
+    }
+
+    #[test]
+    fn reject_code_cbor() {
+        let cbor = serde_cbor::to_vec(&RejectCode::CanisterReject).unwrap();
+        assert_eq!(cbor, serde_cbor::to_vec(&4u64).unwrap());
+        assert_eq!(
+            serde_cbor::from_slice::<RejectCode>(&cbor).unwrap(),
+            RejectCode::CanisterReject
+        );
+        assert_eq!(
+            serde_cbor::from_slice::<RejectCode>(&serde_cbor::to_vec(&42u64).unwrap()).unwrap(),
+            RejectCode::Unknown(42)
+        );
+    }
+}
@@ -1,7 +1,7 @@
 use crate::{AgentError, RequestId};
 
 use crate::agent::replica_api::Certificate;
-use crate::agent::{Replied, RequestStatusResponse};
+use crate::agent::{RejectCode, Replied, RequestStatusResponse};
 use crate::bls::bls12381::bls;
 use crate::hash_tree::{Label, LookupResult};
 use std::str::from_utf8;
@@ -43,6 +43,19 @@ pub fn extract_der(buf: Vec<u8>) -> Result<Vec<u8>, AgentError> {
     Ok(key.to_vec())
 }
 
+/// Accepts a BLS public key either DER-encoded or as the raw key bytes, and returns
+/// its DER encoding.
+pub fn der_encode_bls_key(buf: Vec<u8>) -> Result<Vec<u8>, AgentError> {
+    if buf.len() == KEY_LENGTH {
+        let mut der = DER_PREFIX.to_vec();
+        der.extend_from_slice(&buf);
+        Ok(der)
+    } else {
+        extract_der(buf.clone())?;
+        Ok(buf)
+    }
+}
+
 pub(crate) fn lookup_canister_info(
     certificate: Certificate,
     canister_id: ic_types::Principal,
@@ -52,6 +65,26 @@ pub(crate) fn lookup_canister_info(
     lookup_value(&certificate, path_canister).map(<[u8]>::to_vec)
 }
 
+pub(crate) fn lookup_time(certificate: &Certificate) -> Result<u64, AgentError> {
+    let time = lookup_value(&certificate, vec!["time".into()])?;
+    let mut readable = &time[..];
+    Ok(leb128::read::unsigned(&mut readable)?)
+}
+
+/// Lookup the canister id ranges a subnet is authoritative for, in a (delegation) certificate.
+pub(crate) fn lookup_canister_ranges(
+    certificate: &Certificate,
+    subnet_id: &[u8],
+) -> Result<Vec<(Vec<u8>, Vec<u8>)>, AgentError> {
+    let path = vec!["subnet".into(), subnet_id.into(), "canister_ranges".into()];
+    let ranges: Vec<(serde_bytes::ByteBuf, serde_bytes::ByteBuf)> =
+        serde_cbor::from_slice(lookup_value(&certificate, path)?)?;
+    Ok(ranges
+        .into_iter()
+        .map(|(low, high)| (low.into_vec(), high.into_vec()))
+        .collect())
+}
+
 pub(crate) fn lookup_request_status(
     certificate: Certificate,
     request_id: &RequestId,
@@ -95,7 +128,7 @@ pub(crate) fn lookup_rejection(
 pub(crate) fn lookup_reject_code(
     certificate: &Certificate,
     request_id: &RequestId,
-) -> Result<u64, AgentError> {
+) -> Result<RejectCode, AgentError> {
     let path = vec![
         "request_status".into(),
         request_id.to_vec().into(),
@@ -103,7 +136,8 @@ pub(crate) fn lookup_reject_code(
     ];
     let code = lookup_value(&certificate, path)?;
     let mut readable = &code[..];
-    Ok(leb128::read::unsigned(&mut readable)?)
+    let code = leb128::read::unsigned(&mut readable)?;
+    Ok(RejectCode::from(code))
 }
 
 pub(crate) fn lookup_reject_message(
@@ -0,0 +1,105 @@
+//! Requests signed ahead of time, to be submitted later, possibly by another machine.
+//!
+//! This allows signing requests on a machine that holds the key but is not connected to
+//! the network, and submitting them from one that is connected but does not hold the key.
+//! The signed requests are meant to be serialized in a binary format, such as CBOR.
+use crate::export::Principal;
+use crate::RequestId;
+use serde::{Deserialize, Serialize};
+
+/// An update call signed with [UpdateBuilder::sign](super::UpdateBuilder::sign), to be
+/// submitted with [Agent::submit_update](super::Agent::submit_update).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedUpdate {
+    /// The time (in nanoseconds since the epoch) the request expires at. It must be
+    /// submitted before then.
+    pub ingress_expiry: u64,
+    /// The principal sending the request.
+    pub sender: Principal,
+    /// The canister called.
+    pub canister_id: Principal,
+    /// The method called.
+    pub method_name: String,
+    /// The argument of the call.
+    #[serde(with = "serde_bytes")]
+    pub arg: Vec<u8>,
+    /// The effective canister id, used to route the request.
+    pub effective_canister_id: Principal,
+    /// The id of the request, needed to read its status.
+    #[serde(with = "request_id_bytes")]
+    pub request_id: RequestId,
+    /// The CBOR-encoded envelope of the request.
+    #[serde(with = "serde_bytes")]
+    pub signed_update: Vec<u8>,
+}
+
+/// A query call signed with [QueryBuilder::sign](super::QueryBuilder::sign), to be
+/// submitted with [Agent::submit_query](super::Agent::submit_query).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedQuery {
+    /// The time (in nanoseconds since the epoch) the request expires at. It must be
+    /// submitted before then.
+    pub ingress_expiry: u64,
+    /// The principal sending the request.
+    pub sender: Principal,
+    /// The canister called.
+    pub canister_id: Principal,
+    /// The method called.
+    pub method_name: String,
+    /// The argument of the call.
+    #[serde(with = "serde_bytes")]
+    pub arg: Vec<u8>,
+    /// The effective canister id, used to route the request.
+    pub effective_canister_id: Principal,
+    /// The id of the request.
+    #[serde(with = "request_id_bytes")]
+    pub request_id: RequestId,
+    /// The CBOR-encoded envelope of the request.
+    #[serde(with = "serde_bytes")]
+    pub signed_query: Vec<u8>,
+}
+
+/// A request for the status of an update call, signed with
+/// [Agent::sign_request_status](super::Agent::sign_request_status), to be submitted with
+/// [Agent::submit_request_status](super::Agent::submit_request_status).
+///
+/// Unlike calls, it can be submitted any number of times until it expires.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedRequestStatus {
+    /// The time (in nanoseconds since the epoch) the request expires at. It must be
+    /// submitted before then.
+    pub ingress_expiry: u64,
+    /// The principal sending the request, which must be the sender of the update call.
+    pub sender: Principal,
+    /// The effective canister id, used to route the request.
+    pub effective_canister_id: Principal,
+    /// The id of the update call whose status is requested.
+    #[serde(with = "request_id_bytes")]
+    pub request_id: RequestId,
+    /// The CBOR-encoded envelope of the request.
+    #[serde(with = "serde_bytes")]
+    pub signed_request_status: Vec<u8>,
+}
+
+/// Serialize a [RequestId] as its bytes, and read it back.
+mod request_id_bytes {
+    use crate::RequestId;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        request_id: &RequestId,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(request_id.as_slice())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<RequestId, D::Error> {
+        let bytes = serde_bytes::ByteBuf::deserialize(deserializer)?;
+        if bytes.len() != 32 {
+            return Err(D::Error::invalid_length(bytes.len(), &"32 bytes"));
+        }
+        let mut blob = [0; 32];
+        blob.copy_from_slice(&bytes);
+        Ok(RequestId::new(&blob))
+    }
+}
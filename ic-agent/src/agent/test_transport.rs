@@ -0,0 +1,112 @@
+//! A [ReplicaV2Transport] answering requests with canned responses, for the tests of the
+//! agent and of the transports wrapping other transports.
+use crate::agent::agent_error::HttpErrorPayload;
+use crate::agent::ReplicaV2Transport;
+use crate::export::Principal;
+use crate::{AgentError, RequestId};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+type Answer = Arc<dyn Fn() -> Result<Vec<u8>, AgentError> + Send + Sync>;
+
+/// The envelopes sent to a [TestTransport], along with the request id of calls.
+pub(crate) type Requests = Arc<Mutex<Vec<(Vec<u8>, Option<RequestId>)>>>;
+
+/// A transport answering every request with the same response, and recording the envelopes
+/// sent to it.
+pub(crate) struct TestTransport {
+    answer: Answer,
+    query_answer: Option<Answer>,
+    requests: Requests,
+}
+
+impl TestTransport {
+    /// Answer every request with the result of `answer`.
+    pub(crate) fn new<F>(answer: F) -> Self
+    where
+        F: 'static + Fn() -> Result<Vec<u8>, AgentError> + Send + Sync,
+    {
+        Self {
+            answer: Arc::new(answer),
+            query_answer: None,
+            requests: Default::default(),
+        }
+    }
+
+    /// Answer queries with the result of `answer` instead.
+    pub(crate) fn with_query_answer<F>(self, answer: F) -> Self
+    where
+        F: 'static + Fn() -> Result<Vec<u8>, AgentError> + Send + Sync,
+    {
+        Self {
+            query_answer: Some(Arc::new(answer)),
+            ..self
+        }
+    }
+
+    /// The requests sent to the transport, shared with it.
+    pub(crate) fn requests(&self) -> Requests {
+        self.requests.clone()
+    }
+
+    fn answer(
+        &self,
+        answer: &Answer,
+        envelope: Vec<u8>,
+        request_id: Option<RequestId>,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, AgentError>> + Send>> {
+        self.requests.lock().unwrap().push((envelope, request_id));
+        let result = answer();
+        Box::pin(async move { result })
+    }
+}
+
+/// The error of a transport which cannot reach the replica.
+pub(crate) fn connection_error() -> AgentError {
+    AgentError::TransportError("Connection refused.".into())
+}
+
+/// The error of a replica answering with a 503 status.
+pub(crate) fn unavailable() -> AgentError {
+    AgentError::HttpError(HttpErrorPayload {
+        status: 503,
+        content_type: None,
+        content: vec![],
+    })
+}
+
+impl ReplicaV2Transport for TestTransport {
+    fn call<'a>(
+        &'a self,
+        _effective_canister_id: Principal,
+        envelope: Vec<u8>,
+        request_id: RequestId,
+    ) -> Pin<Box<dyn Future<Output = Result<(), AgentError>> + Send + 'a>> {
+        let answer = self.answer(&self.answer, envelope, Some(request_id));
+        Box::pin(async move { answer.await.map(|_| ()) })
+    }
+
+    fn read_state<'a>(
+        &'a self,
+        _effective_canister_id: Principal,
+        envelope: Vec<u8>,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, AgentError>> + Send + 'a>> {
+        self.answer(&self.answer, envelope, None)
+    }
+
+    fn query<'a>(
+        &'a self,
+        _effective_canister_id: Principal,
+        envelope: Vec<u8>,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, AgentError>> + Send + 'a>> {
+        let answer = self.query_answer.as_ref().unwrap_or(&self.answer);
+        self.answer(answer, envelope, None)
+    }
+
+    fn status<'a>(
+        &'a self,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, AgentError>> + Send + 'a>> {
+        self.answer(&self.answer, vec![], None)
+    }
+}
@@ -0,0 +1,246 @@
+//! A [ReplicaV2Transport] speaking HTTP over a Unix domain socket, to reach a local replica
+//! without a TCP port.
+//!
+//! The transport is built on tokio, and must be used from within a tokio runtime.
+#![cfg(all(unix, feature = "unix-socket"))]
+
+use crate::agent::agent_error::HttpErrorPayload;
+use crate::{AgentError, RequestId};
+use http::Method;
+use ic_types::Principal;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+
+/// The URL scheme selecting this transport, as in `unix:///run/replica.sock`.
+pub const UNIX_SOCKET_SCHEME: &str = "unix";
+
+fn transport_error<E: 'static + std::error::Error + Send + Sync>(error: E) -> AgentError {
+    AgentError::TransportError(Box::new(error))
+}
+
+/// A [ReplicaV2Transport] sending the requests of the `/api/v2/` HTTP interface to a
+/// replica listening on a Unix domain socket. A connection is opened for every request,
+/// and driven by a task spawned on the current tokio runtime.
+pub struct UnixSocketReplicaV2Transport {
+    socket_path: PathBuf,
+}
+
+impl UnixSocketReplicaV2Transport {
+    /// Create a transport to the replica listening on the socket at `socket_path`.
+    pub fn create<P: AsRef<Path>>(socket_path: P) -> Self {
+        Self {
+            socket_path: socket_path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Create a transport from a URL of the form `unix:///path/to/socket`, where the path
+    /// is percent-encoded.
+    pub fn from_url(url: &str) -> Result<Self, AgentError> {
+        let invalid = || AgentError::InvalidReplicaUrl(url.to_string());
+        let parsed = url::Url::parse(url).map_err(|_| invalid())?;
+        if parsed.scheme() != UNIX_SOCKET_SCHEME || parsed.has_host() || parsed.path() == "/" {
+            return Err(invalid());
+        }
+        let socket_path = parsed.to_file_path().map_err(|()| invalid())?;
+        Ok(Self::create(socket_path))
+    }
+
+    /// The path of the socket the replica listens on.
+    pub fn socket_path(&self) -> &Path {
+        &self.socket_path
+    }
+
+    async fn execute(
+        &self,
+        method: Method,
+        endpoint: &str,
+        body: Option<Vec<u8>>,
+    ) -> Result<Vec<u8>, AgentError> {
+        let stream = tokio::net::UnixStream::connect(&self.socket_path)
+            .await
+            .map_err(transport_error)?;
+        let (mut sender, connection) = hyper::client::conn::handshake(stream)
+            .await
+            .map_err(transport_error)?;
+        tokio::spawn(async move {
+            // Errors surface in the response to the request.
+            let _ = connection.await;
+        });
+
+        let http_request = hyper::Request::builder()
+            .method(method)
+            .uri(format!("/api/v2/{}", endpoint))
+            .header(http::header::HOST, "localhost")
+            .header(http::header::CONTENT_TYPE, "application/cbor")
+            .body(body.map_or_else(hyper::Body::empty, hyper::Body::from))
+            .map_err(transport_error)?;
+        let response = sender
+            .send_request(http_request)
+            .await
+            .map_err(transport_error)?;
+
+        let status = response.status();
+        let content_type = response
+            .headers()
+            .get(http::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(|x| x.to_string());
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(transport_error)?
+            .to_vec();
+
+        if status.is_client_error() || status.is_server_error() {
+            Err(AgentError::HttpError(HttpErrorPayload {
+                status: status.into(),
+                content_type,
+                content: body,
+            }))
+        } else {
+            Ok(body)
+        }
+    }
+}
+
+impl super::ReplicaV2Transport for UnixSocketReplicaV2Transport {
+    fn call<'a>(
+        &'a self,
+        effective_canister_id: Principal,
+        envelope: Vec<u8>,
+        _request_id: RequestId,
+    ) -> Pin<Box<dyn Future<Output = Result<(), AgentError>> + Send + 'a>> {
+        Box::pin(async move {
+            let endpoint = format!("canister/{}/call", effective_canister_id.to_text());
+            self.execute(Method::POST, &endpoint, Some(envelope))
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn read_state<'a>(
+        &'a self,
+        effective_canister_id: Principal,
+        envelope: Vec<u8>,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, AgentError>> + Send + 'a>> {
+        Box::pin(async move {
+            let endpoint = format!("canister/{}/read_state", effective_canister_id.to_text());
+            self.execute(Method::POST, &endpoint, Some(envelope)).await
+        })
+    }
+
+    fn query<'a>(
+        &'a self,
+        effective_canister_id: Principal,
+        envelope: Vec<u8>,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, AgentError>> + Send + 'a>> {
+        Box::pin(async move {
+            let endpoint = format!("canister/{}/query", effective_canister_id.to_text());
+            self.execute(Method::POST, &endpoint, Some(envelope)).await
+        })
+    }
+
+    fn status<'a>(
+        &'a self,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, AgentError>> + Send + 'a>> {
+        Box::pin(async move { self.execute(Method::GET, "status", None).await })
+    }
+
+    fn is_local(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UnixSocketReplicaV2Transport;
+    use crate::agent::agent_error::HttpErrorPayload;
+    use crate::agent::ReplicaV2Transport;
+    use crate::export::Principal;
+    use crate::AgentError;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::UnixListener;
+
+    #[test]
+    fn from_url() {
+        let transport = UnixSocketReplicaV2Transport::from_url("unix:///run/replica.sock")
+            .expect("Cannot parse the URL.");
+        assert_eq!(
+            transport.socket_path(),
+            std::path::Path::new("/run/replica.sock")
+        );
+        let transport = UnixSocketReplicaV2Transport::from_url("unix:///run/my%20replica.sock")
+            .expect("Cannot parse the URL.");
+        assert_eq!(
+            transport.socket_path(),
+            std::path::Path::new("/run/my replica.sock")
+        );
+
+        for url in &[
+            "http://localhost:8000",
+            "unix://host/replica.sock",
+            "unix:///",
+            "unix:replica.sock",
+        ] {
+            assert!(matches!(
+                UnixSocketReplicaV2Transport::from_url(url),
+                Err(AgentError::InvalidReplicaUrl(_))
+            ));
+        }
+    }
+
+    /// Answer one HTTP request on the listener with `response`, and return the request.
+    async fn answer(listener: UnixListener, response: &'static str) -> String {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buffer = [0; 1024];
+        while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+            let read = stream.read(&mut buffer).await.unwrap();
+            request.extend_from_slice(&buffer[..read]);
+        }
+        stream.write_all(response.as_bytes()).await.unwrap();
+        stream.shutdown().await.unwrap();
+        String::from_utf8_lossy(&request).into_owned()
+    }
+
+    #[test]
+    fn requests() {
+        let dir = std::env::temp_dir().join(format!("ic-agent-unix-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket_path = dir.join("replica.sock");
+        let _ = std::fs::remove_file(&socket_path);
+
+        let runtime = tokio::runtime::Runtime::new().expect("Unable to create a runtime");
+        runtime.block_on(async {
+            let transport = UnixSocketReplicaV2Transport::create(&socket_path);
+            assert!(transport.is_local());
+
+            let listener = UnixListener::bind(&socket_path).unwrap();
+            let server = tokio::spawn(answer(
+                listener,
+                "HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nhello",
+            ));
+            assert_eq!(transport.status().await.unwrap(), b"hello");
+            let request = server.await.unwrap();
+            assert!(request.starts_with("GET /api/v2/status HTTP/1.1\r\n"));
+
+            std::fs::remove_file(&socket_path).unwrap();
+            let listener = UnixListener::bind(&socket_path).unwrap();
+            let server = tokio::spawn(answer(
+                listener,
+                "HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\n\r\n",
+            ));
+            let result = transport
+                .query(Principal::management_canister(), vec![])
+                .await;
+            assert!(matches!(
+                result,
+                Err(AgentError::HttpError(HttpErrorPayload { status: 503, .. }))
+            ));
+            let request = server.await.unwrap();
+            assert!(request.starts_with("POST /api/v2/canister/aaaaa-aa/query HTTP/1.1\r\n"));
+        });
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
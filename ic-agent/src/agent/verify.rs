@@ -0,0 +1,372 @@
+//! Decoding and verification of signed request envelopes, for auditing messages signed
+//! offline or for implementing replica stand-ins.
+use crate::agent::replica_api::{Envelope, EnvelopeContent};
+use crate::agent::IC_REQUEST_DOMAIN_SEPARATOR;
+use crate::export::Principal;
+use crate::{to_request_id, RequestId, RequestIdError};
+use openssl::bn::BigNum;
+use openssl::ecdsa::EcdsaSig;
+use openssl::nid::Nid;
+use openssl::pkey::{Id, PKey};
+use openssl::sha::sha256;
+use ring::signature::{UnparsedPublicKey, ED25519};
+use thiserror::Error;
+
+/// The DER prefix of an Ed25519 SubjectPublicKeyInfo, followed by the 32 bytes of the key.
+const ED25519_DER_PREFIX: &[u8; 12] = b"\x30\x2a\x30\x05\x06\x03\x2b\x65\x70\x03\x21\x00";
+
+/// An error found while decoding or verifying an envelope.
+#[derive(Error, Debug)]
+pub enum VerifyError {
+    #[error("Invalid CBOR data, could not deserialize: {0}")]
+    InvalidCborData(#[from] serde_cbor::Error),
+
+    #[error("Cannot calculate a RequestID: {0}")]
+    CannotCalculateRequestId(#[from] RequestIdError),
+
+    #[error("The request from {0} is not signed.")]
+    MissingSignature(Principal),
+
+    #[error("The request from the anonymous principal must not be signed.")]
+    UnexpectedSignature,
+
+    #[error("The sender {sender} is not the principal of the public key ({expected}).")]
+    SenderMismatch {
+        sender: Principal,
+        expected: Principal,
+    },
+
+    #[error("Unsupported public key: {0}")]
+    UnsupportedPublicKey(String),
+
+    #[error("The signature of the request is invalid.")]
+    InvalidSignature,
+
+    #[error("The signature of delegation {0} is invalid.")]
+    InvalidDelegationSignature(usize),
+
+    #[error("Delegation {index} does not allow requests to canister {canister_id}.")]
+    DelegationTargetMismatch {
+        index: usize,
+        canister_id: Principal,
+    },
+}
+
+/// Decode a CBOR-encoded envelope, as sent to any of the `call`, `query` or `read_state`
+/// endpoints. The envelope is not verified.
+pub fn decode_envelope(bytes: &[u8]) -> Result<Envelope<EnvelopeContent>, VerifyError> {
+    // Principals only decode from borrowed bytes, so this must read from a slice.
+    Ok(serde_cbor::from_slice(bytes)?)
+}
+
+/// Verify an envelope and return the request id of its content.
+///
+/// A request from the anonymous principal must not be signed. Any other request must be
+/// signed by its sender: `sender` must be the self-authenticating principal of
+/// `sender_pubkey`, each delegation of `sender_delegation` must be signed by the key
+/// delegating, and `sender_sig` must be a signature of the request id by the last key
+/// delegated to (or by `sender_pubkey` without delegations). The expiration of the
+/// delegations is not checked, as it depends on when the request is processed.
+pub fn verify_envelope(envelope: &Envelope<EnvelopeContent>) -> Result<RequestId, VerifyError> {
+    let request_id = to_request_id(&envelope.content)?;
+    let sender = envelope.content.sender();
+
+    let (public_key, signature) = match (&envelope.sender_pubkey, &envelope.sender_sig) {
+        (None, None) if envelope.sender_delegation.is_none() => {
+            return if *sender == Principal::anonymous() {
+                Ok(request_id)
+            } else {
+                Err(VerifyError::MissingSignature(sender.clone()))
+            };
+        }
+        (Some(public_key), Some(signature)) => (public_key, signature),
+        _ if *sender == Principal::anonymous() => return Err(VerifyError::UnexpectedSignature),
+        _ => return Err(VerifyError::MissingSignature(sender.clone())),
+    };
+
+    let expected = Principal::self_authenticating(public_key);
+    if *sender != expected {
+        return Err(VerifyError::SenderMismatch {
+            sender: sender.clone(),
+            expected,
+        });
+    }
+
+    let mut signing_key = public_key;
+    for (index, signed) in envelope.sender_delegation.iter().flatten().enumerate() {
+        let delegation = &signed.delegation;
+        verify_signature(signing_key, &delegation.signable()?, &signed.signature).map_err(|e| {
+            match e {
+                VerifyError::InvalidSignature => VerifyError::InvalidDelegationSignature(index),
+                e => e,
+            }
+        })?;
+        if let (Some(targets), Some(canister_id)) =
+            (&delegation.targets, envelope.content.canister_id())
+        {
+            if !targets.contains(canister_id) {
+                return Err(VerifyError::DelegationTargetMismatch {
+                    index,
+                    canister_id: canister_id.clone(),
+                });
+            }
+        }
+        signing_key = &delegation.pubkey;
+    }
+
+    let mut message = Vec::with_capacity(43);
+    message.extend_from_slice(IC_REQUEST_DOMAIN_SEPARATOR);
+    message.extend_from_slice(request_id.as_slice());
+    verify_signature(signing_key, &message, signature)?;
+
+    Ok(request_id)
+}
+
+/// Verify the signature of a message by a DER-encoded public key. Ed25519 keys, and ECDSA
+/// keys on the secp256k1 or prime256v1 (P-256) curves, are supported. ECDSA signatures are
+/// of the SHA-256 digest of the message, encoded as the 64 bytes of `r` and `s`.
+pub fn verify_signature(
+    der_public_key: &[u8],
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), VerifyError> {
+    if der_public_key.len() == ED25519_DER_PREFIX.len() + 32
+        && der_public_key.starts_with(ED25519_DER_PREFIX)
+    {
+        let raw_public_key = &der_public_key[ED25519_DER_PREFIX.len()..];
+        return UnparsedPublicKey::new(&ED25519, raw_public_key)
+            .verify(message, signature)
+            .map_err(|_| VerifyError::InvalidSignature);
+    }
+
+    let unsupported =
+        |e: openssl::error::ErrorStack| VerifyError::UnsupportedPublicKey(e.to_string());
+    let public_key = PKey::public_key_from_der(der_public_key).map_err(unsupported)?;
+    if public_key.id() != Id::EC {
+        return Err(VerifyError::UnsupportedPublicKey(format!(
+            "{:?} keys are not supported.",
+            public_key.id()
+        )));
+    }
+    let ec_key = public_key.ec_key().map_err(unsupported)?;
+    match ec_key.group().curve_name() {
+        Some(Nid::SECP256K1) | Some(Nid::X9_62_PRIME256V1) => {}
+        curve => {
+            return Err(VerifyError::UnsupportedPublicKey(format!(
+                "The curve {:?} is not supported.",
+                curve
+            )))
+        }
+    }
+
+    if signature.len() != 64 {
+        return Err(VerifyError::InvalidSignature);
+    }
+    let r = BigNum::from_slice(&signature[..32]).map_err(|_| VerifyError::InvalidSignature)?;
+    let s = BigNum::from_slice(&signature[32..]).map_err(|_| VerifyError::InvalidSignature)?;
+    let ecdsa_sig =
+        EcdsaSig::from_private_components(r, s).map_err(|_| VerifyError::InvalidSignature)?;
+    match ecdsa_sig.verify(&sha256(message), &ec_key) {
+        Ok(true) => Ok(()),
+        _ => Err(VerifyError::InvalidSignature),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::replica_api::CallRequestContent;
+    use crate::identity::{
+        AnonymousIdentity, BasicIdentity, Delegation, DelegationIdentity, Prime256v1Identity,
+        Secp256k1Identity,
+    };
+    use crate::Identity;
+    use serde::Serialize;
+
+    fn call_content(sender: Principal) -> EnvelopeContent {
+        EnvelopeContent::Call {
+            nonce: Some(vec![1, 2, 3]),
+            ingress_expiry: 1_000_000_000,
+            sender,
+            canister_id: Principal::management_canister(),
+            method_name: "greet".to_string(),
+            arg: b"DIDL\x00\x00".to_vec(),
+        }
+    }
+
+    /// Sign and serialize the content the same way the agent does.
+    fn sign(identity: &dyn Identity, content: EnvelopeContent) -> Vec<u8> {
+        let request_id = to_request_id(&content).unwrap();
+        let mut message = IC_REQUEST_DOMAIN_SEPARATOR.to_vec();
+        message.extend_from_slice(request_id.as_slice());
+        let signature = identity.sign(&message).unwrap();
+        let envelope = Envelope {
+            content,
+            sender_pubkey: signature.public_key,
+            sender_sig: signature.signature,
+            sender_delegation: signature.delegations,
+        };
+        let mut bytes = Vec::new();
+        let mut serializer = serde_cbor::Serializer::new(&mut bytes);
+        serializer.self_describe().unwrap();
+        envelope.serialize(&mut serializer).unwrap();
+        bytes
+    }
+
+    fn sign_and_verify(identity: &dyn Identity) {
+        let content = call_content(identity.sender().unwrap());
+        let expected_request_id = to_request_id(&content).unwrap();
+        let envelope = decode_envelope(&sign(identity, content.clone())).unwrap();
+        assert_eq!(envelope.content, content);
+        assert_eq!(verify_envelope(&envelope).unwrap(), expected_request_id);
+    }
+
+    #[test]
+    fn verify_ed25519() {
+        sign_and_verify(&BasicIdentity::generate().unwrap());
+    }
+
+    #[test]
+    fn verify_secp256k1() {
+        sign_and_verify(&Secp256k1Identity::generate().unwrap());
+    }
+
+    #[test]
+    fn verify_prime256v1() {
+        sign_and_verify(&Prime256v1Identity::generate().unwrap());
+    }
+
+    #[test]
+    fn verify_anonymous() {
+        sign_and_verify(&AnonymousIdentity);
+    }
+
+    #[test]
+    fn verify_query_and_read_state() {
+        let identity = Secp256k1Identity::generate().unwrap();
+        let sender = identity.sender().unwrap();
+        let query = EnvelopeContent::Query {
+            nonce: None,
+            ingress_expiry: 1_000_000_000,
+            sender: sender.clone(),
+            canister_id: Principal::management_canister(),
+            method_name: "greet".to_string(),
+            arg: vec![],
+        };
+        let read_state = EnvelopeContent::ReadState {
+            ingress_expiry: 1_000_000_000,
+            sender,
+            paths: vec![vec!["time".into()]],
+        };
+        for content in vec![query, read_state] {
+            let envelope = decode_envelope(&sign(&identity, content.clone())).unwrap();
+            assert_eq!(envelope.content, content);
+            verify_envelope(&envelope).unwrap();
+        }
+    }
+
+    #[test]
+    fn verify_query_with_nonce() {
+        let identity = Secp256k1Identity::generate().unwrap();
+        let query = |nonce: Option<Vec<u8>>| EnvelopeContent::Query {
+            nonce,
+            ingress_expiry: 1_000_000_000,
+            sender: identity.sender().unwrap(),
+            canister_id: Principal::management_canister(),
+            method_name: "greet".to_string(),
+            arg: vec![],
+        };
+
+        let content = query(Some(vec![1, 2, 3]));
+        let envelope = decode_envelope(&sign(&identity, content.clone())).unwrap();
+        assert_eq!(envelope.content, content);
+        let request_id = verify_envelope(&envelope).unwrap();
+        assert_ne!(request_id, to_request_id(&query(None)).unwrap());
+    }
+
+    #[test]
+    fn request_id_matches_agent_content() {
+        let sender = BasicIdentity::generate().unwrap().sender().unwrap();
+        let content = CallRequestContent::CallRequest {
+            nonce: None,
+            ingress_expiry: 1_000_000_000,
+            sender: sender.clone(),
+            canister_id: Principal::management_canister(),
+            method_name: "greet".to_string(),
+            arg: vec![],
+        };
+        let bytes = serde_cbor::to_vec(&content).unwrap();
+        let decoded: EnvelopeContent = serde_cbor::from_slice(&bytes).unwrap();
+        assert_eq!(
+            to_request_id(&decoded).unwrap(),
+            to_request_id(&content).unwrap()
+        );
+    }
+
+    #[test]
+    fn reject_tampered_content() {
+        let identity = BasicIdentity::generate().unwrap();
+        let mut envelope =
+            decode_envelope(&sign(&identity, call_content(identity.sender().unwrap()))).unwrap();
+        if let EnvelopeContent::Call { arg, .. } = &mut envelope.content {
+            arg.push(0);
+        }
+        assert!(matches!(
+            verify_envelope(&envelope),
+            Err(VerifyError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn reject_wrong_sender() {
+        let identity = Prime256v1Identity::generate().unwrap();
+        let other = Prime256v1Identity::generate().unwrap().sender().unwrap();
+        let envelope = decode_envelope(&sign(&identity, call_content(other))).unwrap();
+        assert!(matches!(
+            verify_envelope(&envelope),
+            Err(VerifyError::SenderMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn reject_unsigned() {
+        let sender = BasicIdentity::generate().unwrap().sender().unwrap();
+        let envelope = decode_envelope(&sign(&AnonymousIdentity, call_content(sender))).unwrap();
+        assert!(matches!(
+            verify_envelope(&envelope),
+            Err(VerifyError::MissingSignature(_))
+        ));
+    }
+
+    #[test]
+    fn verify_delegation_chain() {
+        let root = BasicIdentity::generate().unwrap();
+        let session = Secp256k1Identity::generate().unwrap();
+        let delegation = Delegation {
+            pubkey: session.public_key().unwrap(),
+            expiration: 2_000_000_000,
+            targets: Some(vec![Principal::management_canister()]),
+        };
+        let signed = delegation.sign(&root).unwrap();
+        let identity = DelegationIdentity::new(root.public_key().unwrap(), vec![signed], session);
+        sign_and_verify(&identity);
+
+        let mut envelope =
+            decode_envelope(&sign(&identity, call_content(identity.sender().unwrap()))).unwrap();
+        if let EnvelopeContent::Call { canister_id, .. } = &mut envelope.content {
+            *canister_id = Principal::anonymous();
+        }
+        assert!(matches!(
+            verify_envelope(&envelope),
+            Err(VerifyError::DelegationTargetMismatch { index: 0, .. })
+        ));
+
+        let mut envelope =
+            decode_envelope(&sign(&identity, call_content(identity.sender().unwrap()))).unwrap();
+        envelope.sender_delegation.as_mut().unwrap()[0].signature[0] ^= 1;
+        assert!(matches!(
+            verify_envelope(&envelope),
+            Err(VerifyError::InvalidDelegationSignature(0))
+        ));
+    }
+}
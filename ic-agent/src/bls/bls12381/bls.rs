@@ -176,3 +176,47 @@ pub fn core_verify(sig: &[u8], m: &[u8], w: &[u8]) -> isize {
     }
     return BLS_FAIL;
 }
+
+/* A public key decoded, checked and prepared once, for repeated verifications */
+
+pub struct PreparedPublicKey {
+    table: [FP4; ecp::G2_TABLE],
+}
+
+/* Decode the public key w, check it is in G2, and precompute its line functions */
+
+pub fn prepare_public_key(w: &[u8]) -> Option<PreparedPublicKey> {
+    let pk = ECP2::frombytes(&w);
+    if !pair::g2member(&pk) {
+        return None;
+    }
+    let mut table = [FP4::new(); ecp::G2_TABLE];
+    pair::precomp(&mut table, &pk);
+    return Some(PreparedPublicKey { table });
+}
+
+/* Verify signature given message m, the signature sig, and a prepared public key */
+
+pub fn core_verify_prepared(sig: &[u8], m: &[u8], pk: &PreparedPublicKey) -> isize {
+    let hm = bls_hash_to_point(m);
+
+    let mut d = ECP::frombytes(&sig);
+    if !pair::g1member(&d) {
+        return BLS_FAIL;
+    }
+    d.neg();
+
+    // Both G2 points are precomputed, so use the multi-pairing mechanism
+    let mut r = pair::initmp();
+    unsafe {
+        pair::another_pc(&mut r, &G2_TAB, &d);
+    }
+    pair::another_pc(&mut r, &pk.table, &hm);
+    let mut v = pair::miller(&mut r);
+
+    v = pair::fexp(&v);
+    if v.isunity() {
+        return BLS_OK;
+    }
+    return BLS_FAIL;
+}
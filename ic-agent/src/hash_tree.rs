@@ -1,5 +1,5 @@
-//! Types used to manage the Hash Tree. This type is currently private and should be improved
-//! and reviewed before being moved to ic-types (where it should ultimately live).
+//! Types used to manage the Hash Tree. These types are returned as part of a verified
+//! [Certificate](crate::agent::Certificate), and should ultimately live in ic-types.
 //!
 //! TODO: clean this file and move it to ic-types. When this is done, consider generalizing
 //!       the Sha256Digest and use the same type in RequestId (they're interchangeable).
@@ -110,6 +110,18 @@ impl HashTree {
     {
         self.root.lookup_path(path.as_ref())
     }
+
+    /// Build the full tree holding each value at its path, with the labels sorted as the
+    /// replica sorts them. No path may be a prefix of another one.
+    pub(crate) fn from_leaves(leaves: &[(Vec<Label>, Vec<u8>)]) -> Self {
+        let leaves: Vec<(&[Label], &[u8])> = leaves
+            .iter()
+            .map(|(path, value)| (path.as_slice(), value.as_slice()))
+            .collect();
+        HashTree {
+            root: HashTreeNode::from_leaves(leaves),
+        }
+    }
 }
 
 impl Serialize for HashTree {
@@ -220,6 +232,29 @@ impl std::fmt::Debug for HashTreeNode {
 }
 
 impl HashTreeNode {
+    /// Build the node holding each value at its path, relative to this node.
+    fn from_leaves(leaves: Vec<(&[Label], &[u8])>) -> Self {
+        let mut children: std::collections::BTreeMap<&Label, Vec<(&[Label], &[u8])>> =
+            Default::default();
+        for (path, value) in leaves {
+            match path.split_first() {
+                None => return HashTreeNode::Leaf(value.to_vec()),
+                Some((label, rest)) => children.entry(label).or_default().push((rest, value)),
+            }
+        }
+
+        children
+            .into_iter()
+            .map(|(label, leaves)| {
+                HashTreeNode::Labeled(label.clone(), Box::new(HashTreeNode::from_leaves(leaves)))
+            })
+            .fold(None, |tree, node| match tree {
+                None => Some(node),
+                Some(tree) => Some(HashTreeNode::Fork(Box::new((tree, node)))),
+            })
+            .unwrap_or(HashTreeNode::Empty())
+    }
+
     /// Update a hasher with the domain separator (byte(|s|) . s).
     #[inline]
     fn domain_sep(&self, hasher: &mut Sha256) {
@@ -1,18 +1,23 @@
 use crate::export::Principal;
-use crate::identity::Identity;
+use crate::identity::{Identity, IdentityError};
 use crate::Signature;
 
 pub struct AnonymousIdentity;
 
 impl Identity for AnonymousIdentity {
-    fn sender(&self) -> Result<Principal, String> {
+    fn sender(&self) -> Result<Principal, IdentityError> {
         Ok(Principal::anonymous())
     }
 
-    fn sign(&self, _blob: &[u8]) -> Result<Signature, String> {
+    fn public_key(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn sign(&self, _blob: &[u8]) -> Result<Signature, IdentityError> {
         Ok(Signature {
             signature: None,
             public_key: None,
+            delegations: None,
         })
     }
 }
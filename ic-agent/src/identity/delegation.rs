@@ -0,0 +1,156 @@
+use crate::export::Principal;
+use crate::{to_request_id, Identity, IdentityError, RequestIdError, Signature};
+use serde::{Deserialize, Serialize};
+
+const IC_REQUEST_AUTH_DELEGATION_DOMAIN_SEPARATOR: &[u8; 27] = b"\x1Aic-request-auth-delegation";
+
+/// A delegation from one key to another: the holder of the key delegating (the signer of
+/// the [SignedDelegation]) allows `pubkey` to sign requests on its behalf, until
+/// `expiration`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Delegation {
+    /// The DER-encoded public key the delegation is given to.
+    #[serde(with = "serde_bytes")]
+    pub pubkey: Vec<u8>,
+
+    /// The time (in nanoseconds since the epoch) the delegation expires at.
+    pub expiration: u64,
+
+    /// If set, the delegation is only valid for requests to these canisters.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub targets: Option<Vec<Principal>>,
+}
+
+impl Delegation {
+    /// Returns the message to sign for this delegation, i.e. the domain separator followed
+    /// by the representation-independent hash of the delegation.
+    pub fn signable(&self) -> Result<Vec<u8>, RequestIdError> {
+        let hash = to_request_id(self)?;
+        let mut bytes = Vec::with_capacity(59);
+        bytes.extend_from_slice(IC_REQUEST_AUTH_DELEGATION_DOMAIN_SEPARATOR);
+        bytes.extend_from_slice(hash.as_slice());
+        Ok(bytes)
+    }
+
+    /// Sign the delegation with the identity delegating.
+    pub fn sign(self, identity: &dyn Identity) -> Result<SignedDelegation, IdentityError> {
+        let signable = self
+            .signable()
+            .map_err(|e| IdentityError::SigningFailed(e.to_string()))?;
+        let signature = identity.sign(&signable)?.signature.ok_or_else(|| {
+            IdentityError::SigningFailed("The identity did not produce a signature.".to_string())
+        })?;
+        Ok(SignedDelegation {
+            delegation: self,
+            signature,
+        })
+    }
+}
+
+/// A [Delegation], along with the signature of the key delegating.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedDelegation {
+    pub delegation: Delegation,
+    #[serde(with = "serde_bytes")]
+    pub signature: Vec<u8>,
+}
+
+/// An identity signing with a key that was delegated to by another key, possibly through a
+/// chain of delegations.
+///
+/// The sender of the requests is the principal of the key at the start of the chain, while
+/// requests are signed by `inner`, which should hold the key at the end of the chain.
+pub struct DelegationIdentity<I: Identity> {
+    inner: I,
+    public_key: Vec<u8>,
+    delegations: Vec<SignedDelegation>,
+}
+
+impl<I: Identity> DelegationIdentity<I> {
+    /// Create an identity from the DER-encoded public key at the start of the delegation
+    /// chain, the chain of delegations (starting with the one signed by that key), and the
+    /// identity holding the key delegated to last.
+    pub fn new(public_key: Vec<u8>, delegations: Vec<SignedDelegation>, inner: I) -> Self {
+        Self {
+            inner,
+            public_key,
+            delegations,
+        }
+    }
+
+    /// The chain of delegations, starting from the public key of the sender.
+    pub fn delegations(&self) -> &[SignedDelegation] {
+        &self.delegations
+    }
+}
+
+impl<I: Identity> Identity for DelegationIdentity<I> {
+    fn sender(&self) -> Result<Principal, IdentityError> {
+        Ok(Principal::self_authenticating(&self.public_key))
+    }
+
+    fn public_key(&self) -> Option<Vec<u8>> {
+        Some(self.public_key.clone())
+    }
+
+    fn sign(&self, blob: &[u8]) -> Result<Signature, IdentityError> {
+        let signature = self.inner.sign(blob)?;
+        Ok(Signature {
+            public_key: Some(self.public_key.clone()),
+            signature: signature.signature,
+            delegations: Some(self.delegations.clone()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Delegation, DelegationIdentity};
+    use crate::export::Principal;
+    use crate::identity::BasicIdentity;
+    use crate::Identity;
+    use ring::signature::{Ed25519KeyPair, UnparsedPublicKey, ED25519};
+
+    fn create_identity() -> (BasicIdentity, Vec<u8>) {
+        let rng = ring::rand::SystemRandom::new();
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let identity = BasicIdentity::from_key_pair(key_pair);
+        let public_key = identity.sign(&[]).unwrap().public_key.unwrap();
+        (identity, public_key)
+    }
+
+    #[test]
+    fn delegation_chain() {
+        let (root, root_public_key) = create_identity();
+        let (session, session_public_key) = create_identity();
+
+        let delegation = Delegation {
+            pubkey: session_public_key.clone(),
+            expiration: 1_000_000_000,
+            targets: Some(vec![Principal::management_canister()]),
+        };
+        let signed = delegation.clone().sign(&root).unwrap();
+
+        // The raw Ed25519 public key is the end of its DER encoding.
+        let raw_root_key = &root_public_key[root_public_key.len() - 32..];
+        UnparsedPublicKey::new(&ED25519, raw_root_key)
+            .verify(&delegation.signable().unwrap(), &signed.signature)
+            .expect("The delegation signature is invalid.");
+
+        let identity =
+            DelegationIdentity::new(root_public_key.clone(), vec![signed.clone()], session);
+        assert_eq!(
+            identity.sender().unwrap(),
+            Principal::self_authenticating(&root_public_key)
+        );
+
+        let signature = identity.sign(b"message").unwrap();
+        assert_eq!(signature.public_key, Some(root_public_key));
+        assert_eq!(signature.delegations, Some(vec![signed]));
+        let raw_session_key = &session_public_key[session_public_key.len() - 32..];
+        UnparsedPublicKey::new(&ED25519, raw_session_key)
+            .verify(b"message", &signature.signature.unwrap())
+            .expect("The request signature is invalid.");
+    }
+}
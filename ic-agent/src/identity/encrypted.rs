@@ -0,0 +1,49 @@
+//! Helpers to read and write password-protected (encrypted PKCS#8) private keys.
+use crate::identity::error::PemError;
+
+use openssl::error::ErrorStack;
+use openssl::pkey::{PKey, PKeyRef, Private};
+use openssl::symm::Cipher;
+
+/// Read a private key from a PEM file, which may be an encrypted PKCS#8 file
+/// (`ENCRYPTED PRIVATE KEY`, PBES2 with scrypt or PBKDF2 and AES). `password_fn` is only
+/// called if the key is encrypted.
+pub(crate) fn private_key_from_pem<PasswordFn>(
+    pem: &[u8],
+    password_fn: PasswordFn,
+) -> Result<PKey<Private>, PemError>
+where
+    PasswordFn: FnOnce() -> Result<String, String>,
+{
+    let mut password_error = None;
+    let result = PKey::private_key_from_pem_callback(pem, |buffer| match password_fn() {
+        Ok(password) if password.len() <= buffer.len() => {
+            buffer[..password.len()].copy_from_slice(password.as_bytes());
+            Ok(password.len())
+        }
+        Ok(_) => {
+            password_error = Some(PemError::PasswordTooLong);
+            Err(ErrorStack::get())
+        }
+        Err(e) => {
+            password_error = Some(PemError::PasswordRequired(e));
+            Err(ErrorStack::get())
+        }
+    });
+    match (result, password_error) {
+        (Ok(private_key), _) => Ok(private_key),
+        (Err(_), Some(password_error)) => Err(password_error),
+        (Err(e), None) => Err(PemError::ErrorStack(e)),
+    }
+}
+
+/// Write a private key as an encrypted PKCS#8 PEM file, using PBES2 with PBKDF2 and
+/// AES-256-CBC.
+pub(crate) fn private_key_to_encrypted_pem(
+    private_key: &PKeyRef<Private>,
+    password: &str,
+) -> Result<String, PemError> {
+    let pem = private_key
+        .private_key_to_pem_pkcs8_passphrase(Cipher::aes_256_cbc(), password.as_bytes())?;
+    Ok(String::from_utf8_lossy(&pem).into_owned())
+}
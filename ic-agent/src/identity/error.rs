@@ -1,8 +1,29 @@
-#![cfg(feature = "pem")]
-
 use thiserror::Error;
 
+/// An error happened while an identity computed its principal or signed a message.
+#[derive(Error, Debug)]
+pub enum IdentityError {
+    /// The signer cannot be reached at the moment, e.g. a remote signer is down or a
+    /// hardware token is unplugged. Trying again later might succeed.
+    #[error("The signer is unavailable: {0}")]
+    Unavailable(String),
+
+    /// The signer refused to sign the message, e.g. because its user declined.
+    #[error("The signer refused to sign: {0}")]
+    Refused(String),
+
+    /// The signer failed to produce a signature.
+    #[error("The signer failed to sign: {0}")]
+    SigningFailed(String),
+
+    /// The principal of the identity cannot be computed, e.g. because its public key is
+    /// unknown.
+    #[error("The identity has no valid principal: {0}")]
+    InvalidSender(String),
+}
+
 /// An error happened while reading a PEM file.
+#[cfg(feature = "pem")]
 #[derive(Error, Debug)]
 pub enum PemError {
     #[error(transparent)]
@@ -17,4 +38,16 @@ pub enum PemError {
 
     #[error("A key was rejected by OpenSSL: {0}")]
     ErrorStack(#[from] openssl::error::ErrorStack),
+
+    #[error("The key is on an unsupported curve: {0}")]
+    UnsupportedKeyCurve(String),
+
+    #[error("The key pair cannot be exported, as its private key is unknown.")]
+    KeyNotExportable,
+
+    #[error("A password is required to decrypt the key: {0}")]
+    PasswordRequired(String),
+
+    #[error("The password is too long.")]
+    PasswordTooLong,
 }
@@ -1,17 +1,29 @@
 //! Types and traits dealing with identity across the Internet Computer.
 use crate::export::Principal;
+use std::future::Future;
+use std::pin::Pin;
 
 pub(crate) mod anonymous;
 pub(crate) mod basic;
+pub(crate) mod delegation;
+pub(crate) mod prime256v1;
+#[cfg(all(unix, feature = "remote-signer"))]
+pub mod remote;
 pub(crate) mod secp256k1;
 
 #[cfg(feature = "pem")]
+pub(crate) mod encrypted;
 pub(crate) mod error;
 
 pub use anonymous::AnonymousIdentity;
 pub use basic::BasicIdentity;
+pub use delegation::{Delegation, DelegationIdentity, SignedDelegation};
+pub use prime256v1::Prime256v1Identity;
+#[cfg(all(unix, feature = "remote-signer"))]
+pub use remote::{RemoteIdentity, SignerServer};
 pub use secp256k1::Secp256k1Identity;
 
+pub use error::IdentityError;
 #[cfg(feature = "pem")]
 pub use error::PemError;
 
@@ -20,6 +32,9 @@ pub struct Signature {
     /// This is the DER-encoded public key.
     pub public_key: Option<Vec<u8>>,
     pub signature: Option<Vec<u8>>,
+    /// The chain of delegations from `public_key` to the key that created `signature`, if
+    /// the identity signs with a delegated key.
+    pub delegations: Option<Vec<SignedDelegation>>,
 }
 
 /// An Identity takes a request id and returns the [Signature]. Since it
@@ -30,9 +45,114 @@ pub struct Signature {
 pub trait Identity: Send + Sync {
     /// Returns a sender, ie. the Principal ID that is used to sign a request.
     /// Only one sender can be used per request.
-    fn sender(&self) -> Result<Principal, String>;
+    fn sender(&self) -> Result<Principal, IdentityError>;
+
+    /// Returns the DER-encoded public key of the identity, if it has one and knows it
+    /// without signing.
+    fn public_key(&self) -> Option<Vec<u8>> {
+        None
+    }
 
     /// Sign a blob, the concatenation of the domain separator & request ID,
     /// creating the sender signature.
-    fn sign(&self, blob: &[u8]) -> Result<Signature, String>;
+    fn sign(&self, blob: &[u8]) -> Result<Signature, IdentityError>;
+}
+
+impl<I: Identity + ?Sized> Identity for Box<I> {
+    fn sender(&self) -> Result<Principal, IdentityError> {
+        Identity::sender(&**self)
+    }
+
+    fn public_key(&self) -> Option<Vec<u8>> {
+        Identity::public_key(&**self)
+    }
+
+    fn sign(&self, blob: &[u8]) -> Result<Signature, IdentityError> {
+        Identity::sign(&**self, blob)
+    }
+}
+
+/// An identity that signs asynchronously, for signers that should not block the thread
+/// while signing, e.g. remote signers or hardware tokens.
+///
+/// The identities of this crate sign in memory and implement both traits. Any other
+/// [Identity] can be used by wrapping it in a [SyncIdentity]. The agent only uses this
+/// trait to sign requests.
+pub trait AsyncIdentity: Send + Sync {
+    /// Returns a sender, ie. the Principal ID that is used to sign a request.
+    /// Only one sender can be used per request.
+    fn sender(&self) -> Result<Principal, IdentityError>;
+
+    /// Returns the DER-encoded public key of the identity, if it has one and knows it
+    /// without signing.
+    fn public_key(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Sign a blob, the concatenation of the domain separator & request ID,
+    /// creating the sender signature.
+    fn sign<'a>(
+        &'a self,
+        blob: &'a [u8],
+    ) -> Pin<Box<dyn Future<Output = Result<Signature, IdentityError>> + Send + 'a>>;
+}
+
+/// Implements [AsyncIdentity] for an [Identity] that signs in memory, by signing when
+/// the future is polled.
+macro_rules! impl_async_identity {
+    (impl<$g:ident: $bound:path> $t:ty) => {
+        impl<$g: $bound> AsyncIdentity for $t {
+            impl_async_identity!(@methods);
+        }
+    };
+    ($t:ty) => {
+        impl AsyncIdentity for $t {
+            impl_async_identity!(@methods);
+        }
+    };
+    (@methods) => {
+        fn sender(&self) -> Result<Principal, IdentityError> {
+            Identity::sender(self)
+        }
+
+        fn public_key(&self) -> Option<Vec<u8>> {
+            Identity::public_key(self)
+        }
+
+        fn sign<'a>(
+            &'a self,
+            blob: &'a [u8],
+        ) -> Pin<Box<dyn Future<Output = Result<Signature, IdentityError>> + Send + 'a>> {
+            Box::pin(async move { Identity::sign(self, blob) })
+        }
+    };
+}
+
+impl_async_identity!(AnonymousIdentity);
+impl_async_identity!(BasicIdentity);
+impl_async_identity!(Prime256v1Identity);
+impl_async_identity!(Secp256k1Identity);
+impl_async_identity!(impl<I: Identity> DelegationIdentity<I>);
+
+/// Adapts any [Identity] to an [AsyncIdentity].
+///
+/// The wrapped identity signs on the thread polling the future, so this should only be
+/// used for identities that sign quickly, without I/O.
+pub struct SyncIdentity<I>(pub I);
+
+impl<I: Identity> AsyncIdentity for SyncIdentity<I> {
+    fn sender(&self) -> Result<Principal, IdentityError> {
+        self.0.sender()
+    }
+
+    fn public_key(&self) -> Option<Vec<u8>> {
+        self.0.public_key()
+    }
+
+    fn sign<'a>(
+        &'a self,
+        blob: &'a [u8],
+    ) -> Pin<Box<dyn Future<Output = Result<Signature, IdentityError>> + Send + 'a>> {
+        Box::pin(async move { self.0.sign(blob) })
+    }
 }
@@ -0,0 +1,476 @@
+//! A small protocol to sign over a Unix domain socket, so private keys can be held by a
+//! single long-running process (a signer), like `ssh-agent` does for SSH keys.
+//!
+//! Every message is a CBOR value, prefixed by its length as a 4-byte big-endian integer.
+//! A client sends a [SignerRequest] and reads back a [SignerResponse], as many times as it
+//! needs over the same connection.
+//!
+//! This module is only available with the `remote-signer` feature, which pulls in tokio:
+//! [RemoteIdentity] must be used from within a tokio runtime.
+use crate::export::Principal;
+use crate::identity::{AsyncIdentity, Identity, IdentityError, Signature, SignedDelegation};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// The largest message, in bytes, either side of the protocol accepts.
+const MAX_MESSAGE_SIZE: usize = 1 << 20;
+
+/// How long [RemoteIdentity] waits to connect to the signer.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long [RemoteIdentity] waits for a response of the signer, by default.
+const DEFAULT_RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A request sent to a signer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SignerRequest {
+    /// List the names of the keys held by the signer.
+    ListKeys,
+
+    /// Get the principal and the public key of a key.
+    GetPublicKey { key: String },
+
+    /// Sign a blob with a key.
+    Sign {
+        key: String,
+        #[serde(with = "serde_bytes")]
+        blob: Vec<u8>,
+    },
+}
+
+/// A response of a signer to a [SignerRequest].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SignerResponse {
+    /// The response to [SignerRequest::ListKeys].
+    Keys { keys: Vec<String> },
+
+    /// The response to [SignerRequest::GetPublicKey].
+    PublicKey {
+        sender: Principal,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        #[serde(with = "serde_bytes")]
+        public_key: Option<Vec<u8>>,
+    },
+
+    /// The response to [SignerRequest::Sign].
+    Signature {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        #[serde(with = "serde_bytes")]
+        public_key: Option<Vec<u8>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        #[serde(with = "serde_bytes")]
+        signature: Option<Vec<u8>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        delegations: Option<Vec<SignedDelegation>>,
+    },
+
+    /// The request failed.
+    Error {
+        kind: SignerErrorKind,
+        message: String,
+    },
+}
+
+/// The kind of a [SignerResponse::Error], mirroring the variants of [IdentityError].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SignerErrorKind {
+    Unavailable,
+    Refused,
+    SigningFailed,
+    InvalidSender,
+}
+
+impl From<IdentityError> for SignerResponse {
+    fn from(err: IdentityError) -> Self {
+        let (kind, message) = match err {
+            IdentityError::Unavailable(message) => (SignerErrorKind::Unavailable, message),
+            IdentityError::Refused(message) => (SignerErrorKind::Refused, message),
+            IdentityError::SigningFailed(message) => (SignerErrorKind::SigningFailed, message),
+            IdentityError::InvalidSender(message) => (SignerErrorKind::InvalidSender, message),
+        };
+        SignerResponse::Error { kind, message }
+    }
+}
+
+impl From<Signature> for SignerResponse {
+    fn from(signature: Signature) -> Self {
+        SignerResponse::Signature {
+            public_key: signature.public_key,
+            signature: signature.signature,
+            delegations: signature.delegations,
+        }
+    }
+}
+
+/// Write a message of the protocol to a stream.
+pub fn write_message<W: Write, T: Serialize>(writer: &mut W, message: &T) -> std::io::Result<()> {
+    writer.write_all(&encode_message(message)?)?;
+    writer.flush()
+}
+
+/// Read a message of the protocol from a stream. Returns [None] if the stream was closed
+/// before a new message started.
+pub fn read_message<R: Read, T: DeserializeOwned>(reader: &mut R) -> std::io::Result<Option<T>> {
+    let mut length = [0; 4];
+    match reader.read_exact(&mut length) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let mut bytes = vec![0; message_length(length)?];
+    reader.read_exact(&mut bytes)?;
+    decode_message(&bytes).map(Some)
+}
+
+/// Encode a message, prefixed by its length.
+fn encode_message<T: Serialize>(message: &T) -> std::io::Result<Vec<u8>> {
+    let bytes = serde_cbor::to_vec(message)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    if bytes.len() > MAX_MESSAGE_SIZE {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "The message is too large.",
+        ));
+    }
+    let mut encoded = (bytes.len() as u32).to_be_bytes().to_vec();
+    encoded.extend(bytes);
+    Ok(encoded)
+}
+
+fn message_length(prefix: [u8; 4]) -> std::io::Result<usize> {
+    let length = u32::from_be_bytes(prefix) as usize;
+    if length > MAX_MESSAGE_SIZE {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "The message is too large.",
+        ));
+    }
+    Ok(length)
+}
+
+fn decode_message<T: DeserializeOwned>(bytes: &[u8]) -> std::io::Result<T> {
+    serde_cbor::from_slice(bytes)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+/// The server side of the protocol: serves signing requests for a set of named identities.
+#[derive(Default)]
+pub struct SignerServer {
+    keys: BTreeMap<String, Box<dyn Identity>>,
+}
+
+impl SignerServer {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Serve `identity` under the name `key`, replacing any identity with the same name.
+    pub fn add_key<I: 'static + Identity>(&mut self, key: impl Into<String>, identity: I) {
+        self.keys.insert(key.into(), Box::new(identity));
+    }
+
+    /// Answer a single request.
+    pub fn handle(&self, request: SignerRequest) -> SignerResponse {
+        let identity = |key: &str| {
+            self.keys
+                .get(key)
+                .ok_or_else(|| IdentityError::Refused(format!("Unknown key: {}", key)))
+        };
+        let result = match request {
+            SignerRequest::ListKeys => Ok(SignerResponse::Keys {
+                keys: self.keys.keys().cloned().collect(),
+            }),
+            SignerRequest::GetPublicKey { key } => identity(&key).and_then(|identity| {
+                Ok(SignerResponse::PublicKey {
+                    sender: identity.sender()?,
+                    public_key: identity.public_key(),
+                })
+            }),
+            SignerRequest::Sign { key, blob } => identity(&key)
+                .and_then(|identity| identity.sign(&blob))
+                .map(SignerResponse::from),
+        };
+        result.unwrap_or_else(SignerResponse::from)
+    }
+
+    /// Answer every request sent over a connection, until the client closes it.
+    pub fn serve_connection(&self, mut stream: UnixStream) -> std::io::Result<()> {
+        while let Some(request) = read_message(&mut stream)? {
+            write_message(&mut stream, &self.handle(request))?;
+        }
+        Ok(())
+    }
+}
+
+/// An identity signing through a signer listening on a Unix domain socket.
+///
+/// A new connection is opened for every request, so the identity keeps working if the
+/// signer restarts. The signer is reached asynchronously, so this identity needs a tokio
+/// runtime.
+#[derive(Debug, Clone)]
+pub struct RemoteIdentity {
+    socket_path: PathBuf,
+    key: String,
+    sender: Principal,
+    public_key: Option<Vec<u8>>,
+    response_timeout: Duration,
+}
+
+impl RemoteIdentity {
+    /// Connect to the signer listening on `socket_path`, and use its key named `key`, or
+    /// its first key if [None].
+    pub async fn connect<P: AsRef<Path>>(
+        socket_path: P,
+        key: Option<&str>,
+    ) -> Result<Self, IdentityError> {
+        let socket_path = socket_path.as_ref().to_path_buf();
+        let mut stream = connect(&socket_path).await?;
+
+        let key = match key {
+            Some(key) => key.to_string(),
+            None => match request(
+                &mut stream,
+                &SignerRequest::ListKeys,
+                DEFAULT_RESPONSE_TIMEOUT,
+            )
+            .await?
+            {
+                SignerResponse::Keys { keys } => keys.into_iter().next().ok_or_else(|| {
+                    IdentityError::Unavailable("The signer holds no key.".to_string())
+                })?,
+                response => return Err(unexpected_response(response)),
+            },
+        };
+
+        let get_public_key = SignerRequest::GetPublicKey { key: key.clone() };
+        match request(&mut stream, &get_public_key, DEFAULT_RESPONSE_TIMEOUT).await? {
+            SignerResponse::PublicKey { sender, public_key } => Ok(Self {
+                socket_path,
+                key,
+                sender,
+                public_key,
+                response_timeout: DEFAULT_RESPONSE_TIMEOUT,
+            }),
+            response => Err(unexpected_response(response)),
+        }
+    }
+
+    /// Sets how long to wait for the signer to sign, e.g. for signers asking for a
+    /// confirmation. Defaults to 30 seconds.
+    pub fn with_response_timeout(self, response_timeout: Duration) -> Self {
+        Self {
+            response_timeout,
+            ..self
+        }
+    }
+
+    /// The name of the key used in the signer.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+}
+
+impl AsyncIdentity for RemoteIdentity {
+    fn sender(&self) -> Result<Principal, IdentityError> {
+        Ok(self.sender.clone())
+    }
+
+    fn public_key(&self) -> Option<Vec<u8>> {
+        self.public_key.clone()
+    }
+
+    fn sign<'a>(
+        &'a self,
+        blob: &'a [u8],
+    ) -> Pin<Box<dyn Future<Output = Result<Signature, IdentityError>> + Send + 'a>> {
+        Box::pin(async move {
+            let mut stream = connect(&self.socket_path).await?;
+            let sign = SignerRequest::Sign {
+                key: self.key.clone(),
+                blob: blob.to_vec(),
+            };
+            match request(&mut stream, &sign, self.response_timeout).await? {
+                SignerResponse::Signature {
+                    public_key,
+                    signature,
+                    delegations,
+                } => Ok(Signature {
+                    public_key,
+                    signature,
+                    delegations,
+                }),
+                response => Err(unexpected_response(response)),
+            }
+        })
+    }
+}
+
+async fn connect(socket_path: &Path) -> Result<tokio::net::UnixStream, IdentityError> {
+    let unavailable = |e: std::io::Error| {
+        IdentityError::Unavailable(format!(
+            "Cannot connect to the signer at {}: {}",
+            socket_path.display(),
+            e
+        ))
+    };
+    tokio::time::timeout(
+        CONNECT_TIMEOUT,
+        tokio::net::UnixStream::connect(socket_path),
+    )
+    .await
+    .map_err(|_| unavailable(std::io::ErrorKind::TimedOut.into()))?
+    .map_err(unavailable)
+}
+
+/// Send a request and read its response, turning error responses into errors.
+async fn request(
+    stream: &mut tokio::net::UnixStream,
+    request: &SignerRequest,
+    timeout: Duration,
+) -> Result<SignerResponse, IdentityError> {
+    let unavailable =
+        |e: std::io::Error| IdentityError::Unavailable(format!("Cannot talk to the signer: {}", e));
+    let response = tokio::time::timeout(timeout, exchange_message(stream, request))
+        .await
+        .map_err(|_| unavailable(std::io::ErrorKind::TimedOut.into()))?
+        .map_err(unavailable)?;
+    match response {
+        Some(SignerResponse::Error { kind, message }) => Err(match kind {
+            SignerErrorKind::Unavailable => IdentityError::Unavailable(message),
+            SignerErrorKind::Refused => IdentityError::Refused(message),
+            SignerErrorKind::SigningFailed => IdentityError::SigningFailed(message),
+            SignerErrorKind::InvalidSender => IdentityError::InvalidSender(message),
+        }),
+        Some(response) => Ok(response),
+        None => Err(IdentityError::Unavailable(
+            "The signer closed the connection.".to_string(),
+        )),
+    }
+}
+
+/// Write a request and read its response, or [None] if the signer closed the connection.
+async fn exchange_message(
+    stream: &mut tokio::net::UnixStream,
+    request: &SignerRequest,
+) -> std::io::Result<Option<SignerResponse>> {
+    stream.write_all(&encode_message(request)?).await?;
+    let mut length = [0; 4];
+    match stream.read_exact(&mut length).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let mut bytes = vec![0; message_length(length)?];
+    stream.read_exact(&mut bytes).await?;
+    decode_message(&bytes).map(Some)
+}
+
+fn unexpected_response(response: SignerResponse) -> IdentityError {
+    IdentityError::SigningFailed(format!(
+        "The signer sent an unexpected response: {:?}",
+        response
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RemoteIdentity, SignerServer};
+    use crate::identity::{AnonymousIdentity, BasicIdentity};
+    use crate::{AsyncIdentity, Identity, IdentityError};
+    use ring::signature::{UnparsedPublicKey, ED25519};
+    use std::os::unix::net::UnixListener;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// Start a signer on a new socket, serving connections on a background thread.
+    fn start_signer(server: SignerServer) -> PathBuf {
+        let socket_path = std::env::temp_dir().join(format!(
+            "ic-agent-signer-{}-{}.sock",
+            std::process::id(),
+            rand::random::<u64>()
+        ));
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        let server = Arc::new(server);
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let server = server.clone();
+                std::thread::spawn(move || server.serve_connection(stream.unwrap()));
+            }
+        });
+        socket_path
+    }
+
+    #[tokio::test]
+    async fn remote_identity_signs() {
+        let basic = BasicIdentity::generate().unwrap();
+        let basic_sender = Identity::sender(&basic).unwrap();
+        let basic_public_key = Identity::public_key(&basic).unwrap();
+
+        let mut server = SignerServer::new();
+        server.add_key("a-basic", basic);
+        server.add_key("b-anonymous", AnonymousIdentity);
+        let socket_path = start_signer(server);
+
+        // Without a key name, the first key is used.
+        let identity = RemoteIdentity::connect(&socket_path, None).await.unwrap();
+        assert_eq!(identity.key(), "a-basic");
+        assert_eq!(identity.sender().unwrap(), basic_sender);
+        assert_eq!(identity.public_key(), Some(basic_public_key.clone()));
+
+        let signature = identity.sign(b"message").await.unwrap();
+        assert_eq!(signature.public_key, Some(basic_public_key.clone()));
+        let raw_public_key = &basic_public_key[basic_public_key.len() - 32..];
+        UnparsedPublicKey::new(&ED25519, raw_public_key)
+            .verify(b"message", &signature.signature.unwrap())
+            .expect("The signature is invalid.");
+
+        let anonymous = RemoteIdentity::connect(&socket_path, Some("b-anonymous"))
+            .await
+            .unwrap();
+        assert_eq!(anonymous.public_key(), None);
+        assert_eq!(anonymous.sign(b"message").await.unwrap().signature, None);
+
+        assert!(matches!(
+            RemoteIdentity::connect(&socket_path, Some("unknown")).await,
+            Err(IdentityError::Refused(_))
+        ));
+
+        std::fs::remove_file(&socket_path).unwrap();
+        assert!(matches!(
+            identity.sign(b"message").await,
+            Err(IdentityError::Unavailable(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn remote_identity_times_out() {
+        let mut server = SignerServer::new();
+        server.add_key("basic", BasicIdentity::generate().unwrap());
+        let socket_path = start_signer(server);
+        let identity = RemoteIdentity::connect(&socket_path, None)
+            .await
+            .unwrap()
+            .with_response_timeout(Duration::from_millis(100));
+        std::fs::remove_file(&socket_path).unwrap();
+
+        // A signer that accepts connections but never answers.
+        let _listener = UnixListener::bind(&socket_path).unwrap();
+        assert!(matches!(
+            identity.sign(b"message").await,
+            Err(IdentityError::Unavailable(_))
+        ));
+        std::fs::remove_file(&socket_path).unwrap();
+    }
+}
@@ -44,7 +44,7 @@
 //!   canister_id: Principal,
 //! }
 //!
-//! # fn create_identity() -> impl ic_agent::Identity {
+//! # fn create_identity() -> impl ic_agent::AsyncIdentity {
 //! #     let rng = ring::rand::SystemRandom::new();
 //! #     let key_pair = ring::signature::Ed25519KeyPair::generate_pkcs8(&rng)
 //! #         .expect("Could not generate a key pair.");
@@ -65,10 +65,8 @@
 //!   agent.fetch_root_key().await?;
 //!   let management_canister_id = Principal::from_text("aaaaa-aa")?;
 //!
-//!   let waiter = delay::Delay::builder()
-//!     .throttle(std::time::Duration::from_millis(500))
-//!     .timeout(std::time::Duration::from_secs(60 * 5))
-//!     .build();
+//!   let waiter = ic_agent::agent::Backoff::throttle(std::time::Duration::from_millis(500))
+//!     .with_timeout(std::time::Duration::from_secs(60 * 5));
 //!
 //!   // Create a call to the management canister to create a new canister ID,
 //!   // and wait for a result.
@@ -109,11 +107,10 @@ mod bls;
 
 pub mod agent;
 pub mod export;
+pub mod hash_tree;
 pub mod identity;
 pub mod request_id;
 
 pub use agent::{agent_error, agent_error::AgentError, nonce::NonceFactory, Agent};
-pub use identity::{Identity, Signature};
+pub use identity::{AsyncIdentity, Identity, IdentityError, Signature, SyncIdentity};
 pub use request_id::{to_request_id, RequestId, RequestIdError};
-
-pub(crate) mod hash_tree;
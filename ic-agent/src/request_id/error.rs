@@ -26,8 +26,8 @@ pub enum RequestIdError {
     #[error("RequestId Serializer was in an invalid state")]
     InvalidState,
 
-    #[error("RequestId does not support struct inside other structs")]
-    UnsupportedStructInsideStruct,
+    #[error("Absent (None) values are only supported as values of maps and structs")]
+    UnsupportedAbsentValue,
 
     #[error("Unsupported type: Bool")]
     UnsupportedTypeBool,
@@ -68,204 +68,103 @@ impl Serialize for RequestId {
     }
 }
 
-enum Hasher {
-    /// The hasher for the overall request id.  This is the only part
-    /// that may directly contain a Struct.
-    RequestId(Sha256),
-
-    /// A structure to be included in the hash.  May not contain other structures.
-    Struct {
-        // We use a BTreeMap here as there is no indication that keys might not be duplicated,
-        // and we want to make sure they're overwritten in that case.
-        fields: BTreeMap<Sha256Hash, Sha256Hash>,
-        parent: Box<Hasher>,
-    },
-
-    /// The hasher for a value.  Array elements will append the hash of their
-    /// contents into the hasher of the array.
-    Value(Sha256),
+/// The hash of a value, or `None` if the value is absent (an `Option` that is `None`). Absent
+/// values are left out of the map or structure containing them.
+type ValueHash = Option<Sha256Hash>;
+
+fn hash_bytes(bytes: &[u8]) -> Sha256Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(bytes);
+    hasher.finish()
 }
 
-impl Hasher {
-    fn request_id() -> Hasher {
-        Hasher::RequestId(Sha256::new())
-    }
-
-    fn fields(parent: Box<Hasher>) -> Hasher {
-        Hasher::Struct {
-            fields: BTreeMap::new(),
-            parent,
-        }
-    }
+/// Hash the content of an enum variant, as a map from the name of the variant to its content.
+fn hash_variant(variant: &str, content: ValueHash) -> Result<ValueHash, RequestIdError> {
+    let mut map = MapSerializer::default();
+    map.insert(hash_bytes(variant.as_bytes()), content);
+    map.finish()
+}
 
-    fn value() -> Hasher {
-        Hasher::Value(Sha256::new())
-    }
+/// Hash a value nested in an array or used as a map key, where it cannot be absent.
+fn hash_present<T>(value: &T) -> Result<Sha256Hash, RequestIdError>
+where
+    T: ?Sized + Serialize,
+{
+    value
+        .serialize(RequestIdSerializer)?
+        .ok_or(RequestIdError::UnsupportedAbsentValue)
 }
 
-/// A Serde Serializer that collects fields and values in order to hash them later.
-/// We serialize the type to this structure, then use the trait to hash its content.
-/// It is a simple state machine that contains 3 states:
-///   1. The root value, which is a structure. If a value other than a structure is
-///      serialized, this errors. This is determined by whether `fields` is Some(_).
-///   2. The structure is being processed, and the value of a field is being
-///      serialized. The field_value_hash will be set to Some(_).
-///   3. The finish() function has been called and the hasher cannot be reused. The
-///      hash should have been gotten at this point.
-///
-/// Inconsistent state are when a field is being serialized and `fields` is None, or
-/// when a value (not struct) is being serialized and field_value_hash is None.
+/// A Serde Serializer that computes the representation-independent hash of a value, as
+/// specified for request ids in the public spec:
+///   . Blobs and strings are hashed directly.
+///   . Natural numbers are hashed as their unsigned LEB128 encoding, and integers as their
+///     signed LEB128 encoding. Booleans are hashed as the natural numbers 0 and 1.
+///   . Arrays (sequences, tuples and tuple structs) are hashed as the concatenation of the
+///     hashes of their elements.
+///   . Maps and structures are hashed as the sorted concatenations of the hashes of each key
+///     and value. Fields whose value is `None` are left out.
+///   . Enum variants are hashed like serde_cbor represents them: a unit variant as its name,
+///     and other variants as a map from their name to their content.
 ///
-/// This will always fail on types that are unknown to the Request format (e.g. i8).
-/// An UnsupportedTypeXXX error will be returned.
+/// Newtype structs are hashed as their content, so wrappers like [Principal] or [RequestId]
+/// are hashed as the blob they serialize to.
 ///
-/// The only types that are supported right now are:
-///   . Strings and string slices.
-///   . Vector of u8 (byte strings).
-///   . A structure as the base level. Its typename and fields are not validated.
-///
-/// Additionally, this will fail if there are unsupported data structure, for example
-/// if a UnitVariant of another type than Blob is used, or a structure inside a
-/// structure.
+/// This will fail on types that are unknown to the Request format (e.g. f32). An
+/// UnsupportedTypeXXX error will be returned.
 ///
 /// This does not validate whether a message is valid. This is very important as
 /// the message format might change faster than the ID calculation.
-struct RequestIdSerializer {
-    element_encoder: Option<Hasher>,
-}
-
-impl RequestIdSerializer {
-    pub fn new() -> RequestIdSerializer {
-        Default::default()
-    }
-
-    /// Finish the hashing and returns the RequestId for the structure that was
-    /// serialized.
-    ///
-    /// This can only be called once (it borrows self). Since this whole class is not public,
-    /// it should not be a problem.
-    pub fn finish(self) -> Result<RequestId, RequestIdError> {
-        match self.element_encoder {
-            Some(Hasher::RequestId(hasher)) => Ok(RequestId(hasher.finish())),
-            _ => Err(RequestIdError::EmptySerializer),
-        }
-    }
-
-    /// Hash a single value, returning its sha256_hash. If there is already a value
-    /// being hashed it will return an InvalidState. This cannot happen currently
-    /// as we don't allow embedded structures, but is left as a safeguard when
-    /// making changes.
-    fn hash_value<T>(&mut self, value: &T) -> Result<Sha256Hash, RequestIdError>
-    where
-        T: ?Sized + Serialize,
-    {
-        let prev_encoder = self.element_encoder.take();
-
-        self.element_encoder = Some(Hasher::value());
-
-        value.serialize(&mut *self)?;
-        let result = match self.element_encoder.take() {
-            Some(Hasher::Value(hasher)) => Ok(hasher.finish()),
-            _ => Err(RequestIdError::InvalidState),
-        };
-        self.element_encoder = prev_encoder;
-        result
-    }
-
-    fn hash_fields(&mut self) -> Result<(), RequestIdError> {
-        match self.element_encoder.take() {
-            Some(Hasher::Struct { fields, parent }) => {
-                // Sort the fields.
-                let mut keyvalues: Vec<Vec<u8>> = fields
-                    .keys()
-                    .zip(fields.values())
-                    .map(|(k, v)| {
-                        let mut x = k.to_vec();
-                        x.extend(v);
-                        x
-                    })
-                    .collect();
-                keyvalues.sort();
-
-                let mut parent = *parent;
-
-                match &mut parent {
-                    Hasher::RequestId(hasher) => {
-                        for kv in keyvalues {
-                            hasher.update(&kv);
-                        }
-                        Ok(())
-                    }
-                    _ => Err(RequestIdError::InvalidState),
-                }?;
-
-                self.element_encoder = Some(parent);
-                Ok(())
-            }
-            _ => Err(RequestIdError::InvalidState),
-        }
-    }
-}
-
-impl Default for RequestIdSerializer {
-    fn default() -> RequestIdSerializer {
-        RequestIdSerializer {
-            element_encoder: Some(Hasher::request_id()),
-        }
-    }
-}
+///
+/// [Principal]: crate::export::Principal
+struct RequestIdSerializer;
 
 /// See https://serde.rs/data-format.html for more information on how to implement a
 /// custom data format.
-impl<'a> ser::Serializer for &'a mut RequestIdSerializer {
-    /// The output type produced by this `Serializer` during successful
-    /// serialization. Most serializers that produce text or binary output
-    /// should set `Ok = ()` and serialize into an [`io::Write`] or buffer
-    /// contained within the `Serializer` instance. Serializers that build
-    /// in-memory data structures may be simplified by using `Ok` to propagate
-    /// the data structure around.
-    ///
-    /// [`io::Write`]: https://doc.rust-lang.org/std/io/trait.Write.html
-    type Ok = ();
+impl ser::Serializer for RequestIdSerializer {
+    /// The hash of the value serialized.
+    type Ok = ValueHash;
 
     /// The error type when some error occurs during serialization.
     type Error = RequestIdError;
 
     // Associated types for keeping track of additional state while serializing
-    // compound data structures like sequences and maps. In this case no
-    // additional state is required beyond what is already stored in the
-    // Serializer struct.
-    type SerializeSeq = Self;
-    type SerializeTuple = Self;
-    type SerializeTupleStruct = Self;
-    type SerializeTupleVariant = Self;
-    type SerializeMap = Self;
-    type SerializeStruct = Self;
-    type SerializeStructVariant = Self;
+    // compound data structures like sequences and maps.
+    type SerializeSeq = ArraySerializer;
+    type SerializeTuple = ArraySerializer;
+    type SerializeTupleStruct = ArraySerializer;
+    type SerializeTupleVariant = VariantSerializer<ArraySerializer>;
+    type SerializeMap = MapSerializer;
+    type SerializeStruct = MapSerializer;
+    type SerializeStructVariant = VariantSerializer<MapSerializer>;
 
     /// Serialize a `bool` value.
-    fn serialize_bool(self, _v: bool) -> Result<Self::Ok, Self::Error> {
-        Err(RequestIdError::UnsupportedTypeBool)
+    fn serialize_bool(self, v: bool) -> Result<Self::Ok, Self::Error> {
+        self.serialize_u64(v as u64)
     }
 
     /// Serialize an `i8` value.
-    fn serialize_i8(self, _v: i8) -> Result<Self::Ok, Self::Error> {
-        Err(RequestIdError::UnsupportedTypeI8)
+    fn serialize_i8(self, v: i8) -> Result<Self::Ok, Self::Error> {
+        self.serialize_i64(v as i64)
     }
 
     /// Serialize an `i16` value.
-    fn serialize_i16(self, _v: i16) -> Result<Self::Ok, Self::Error> {
-        Err(RequestIdError::UnsupportedTypeI16)
+    fn serialize_i16(self, v: i16) -> Result<Self::Ok, Self::Error> {
+        self.serialize_i64(v as i64)
     }
 
     /// Serialize an `i32` value.
-    fn serialize_i32(self, _v: i32) -> Result<Self::Ok, Self::Error> {
-        Err(RequestIdError::UnsupportedTypeI32)
+    fn serialize_i32(self, v: i32) -> Result<Self::Ok, Self::Error> {
+        self.serialize_i64(v as i64)
     }
 
     /// Serialize an `i64` value.
-    fn serialize_i64(self, _v: i64) -> Result<Self::Ok, Self::Error> {
-        Err(RequestIdError::UnsupportedTypeI64)
+    fn serialize_i64(self, v: i64) -> Result<Self::Ok, Self::Error> {
+        // 10 bytes is enough for a 64-bit number in leb128.
+        let mut buffer = [0; 10];
+        let mut writable = &mut buffer[..];
+        let n_bytes = leb128::write::signed(&mut writable, v).expect("Could not serialize number.");
+        self.serialize_bytes(&buffer[..n_bytes])
     }
 
     /// Serialize a `u8` value.
@@ -315,23 +214,13 @@ impl<'a> ser::Serializer for &'a mut RequestIdSerializer {
 
     /// Serialize a chunk of raw byte data.
     fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok, Self::Error> {
-        match &mut self.element_encoder {
-            Some(Hasher::RequestId(hasher)) => {
-                hasher.update(v);
-                Ok(())
-            }
-            Some(Hasher::Value(hasher)) => {
-                hasher.update(v);
-                Ok(())
-            }
-            _ => Err(RequestIdError::InvalidState),
-        }
+        Ok(Some(hash_bytes(v)))
     }
 
     /// Serialize a [`None`] value.
     fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
-        // Compute the hash as if it was empty string or blob.
-        Ok(())
+        // The value is absent.
+        Ok(None)
     }
 
     /// Serialize a [`Some(T)`] value.
@@ -358,23 +247,21 @@ impl<'a> ser::Serializer for &'a mut RequestIdSerializer {
         self,
         _name: &'static str,
         _variant_index: u32,
-        _variant: &'static str,
+        variant: &'static str,
     ) -> Result<Self::Ok, Self::Error> {
-        Err(RequestIdError::UnsupportedTypeUnitVariant)
+        self.serialize_str(variant)
     }
 
     /// Serialize a newtype struct like `struct Millimeters(u8)`.
     fn serialize_newtype_struct<T: ?Sized>(
         self,
-        name: &'static str,
-        _value: &T,
+        _name: &'static str,
+        value: &T,
     ) -> Result<Self::Ok, Self::Error>
     where
         T: Serialize,
     {
-        Err(RequestIdError::UnsupportedTypeNewtypeStruct(
-            name.to_owned(),
-        ))
+        value.serialize(self)
     }
 
     /// Serialize a newtype variant like `E::N` in `enum E { N(u8) }`.
@@ -382,20 +269,20 @@ impl<'a> ser::Serializer for &'a mut RequestIdSerializer {
         self,
         _name: &'static str,
         _variant_index: u32,
-        _variant: &'static str,
-        _value: &T,
+        variant: &'static str,
+        value: &T,
     ) -> Result<Self::Ok, Self::Error>
     where
         T: Serialize,
     {
-        Err(RequestIdError::UnsupportedTypeNewTypeVariant)
+        hash_variant(variant, value.serialize(self)?)
     }
 
     /// Begin to serialize a variably sized sequence. This call must be
     /// followed by zero or more calls to `serialize_element`, then a call to
     /// `end`.
     fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
-        Ok(self)
+        Ok(ArraySerializer::default())
     }
 
     /// Begin to serialize a statically sized sequence whose length will be
@@ -403,7 +290,7 @@ impl<'a> ser::Serializer for &'a mut RequestIdSerializer {
     /// This call must be followed by zero or more calls to `serialize_element`,
     /// then a call to `end`.
     fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Self::Error> {
-        Err(RequestIdError::UnsupportedTypeTuple)
+        Ok(ArraySerializer::default())
     }
 
     /// Begin to serialize a tuple struct like `struct Rgb(u8, u8, u8)`. This
@@ -414,7 +301,7 @@ impl<'a> ser::Serializer for &'a mut RequestIdSerializer {
         _name: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeTupleStruct, Self::Error> {
-        Err(RequestIdError::UnsupportedTypeTupleStruct)
+        Ok(ArraySerializer::default())
     }
 
     /// Begin to serialize a tuple variant like `E::T` in `enum E { T(u8, u8)
@@ -424,16 +311,19 @@ impl<'a> ser::Serializer for &'a mut RequestIdSerializer {
         self,
         _name: &'static str,
         _variant_index: u32,
-        _variant: &'static str,
+        variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeTupleVariant, Self::Error> {
-        Err(RequestIdError::UnsupportedTypeTupleVariant)
+        Ok(VariantSerializer {
+            variant,
+            inner: ArraySerializer::default(),
+        })
     }
 
     /// Begin to serialize a map. This call must be followed by zero or more
     /// calls to `serialize_key` and `serialize_value`, then a call to `end`.
     fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
-        Err(RequestIdError::UnsupportedTypeMap)
+        Ok(MapSerializer::default())
     }
 
     /// Begin to serialize a struct like `struct Rgb { r: u8, g: u8, b: u8 }`.
@@ -444,14 +334,7 @@ impl<'a> ser::Serializer for &'a mut RequestIdSerializer {
         _name: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeStruct, Self::Error> {
-        let parent_encoder = self.element_encoder.take();
-        match &parent_encoder {
-            Some(Hasher::RequestId(_)) => {
-                self.element_encoder = Some(Hasher::fields(Box::new(parent_encoder.unwrap())));
-                Ok(self)
-            }
-            _ => Err(RequestIdError::UnsupportedStructInsideStruct),
-        }
+        Ok(MapSerializer::default())
     }
 
     /// Begin to serialize a struct variant like `E::S` in `enum E { S { r: u8,
@@ -461,10 +344,13 @@ impl<'a> ser::Serializer for &'a mut RequestIdSerializer {
         self,
         _name: &'static str,
         _variant_index: u32,
-        _variant: &'static str,
+        variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeStructVariant, Self::Error> {
-        Err(RequestIdError::UnsupportedTypeStructVariant)
+        Ok(VariantSerializer {
+            variant,
+            inner: MapSerializer::default(),
+        })
     }
 
     fn is_human_readable(&self) -> bool {
@@ -472,198 +358,202 @@ impl<'a> ser::Serializer for &'a mut RequestIdSerializer {
     }
 }
 
-// The following 7 impls deal with the serialization of compound types like
-// sequences and maps. Serialization of such types is begun by a Serializer
-// method and followed by zero or more calls to serialize individual elements of
-// the compound type and one call to end the compound type.
-//
-// This impl is SerializeSeq so these methods are called after `serialize_seq`
-// is called on the Serializer.
-impl<'a> ser::SerializeSeq for &'a mut RequestIdSerializer {
-    // Must match the `Ok` type of the serializer.
-    type Ok = ();
-    // Must match the `Error` type of the serializer.
-    type Error = RequestIdError;
+/// Hashes an array: the hashes of its elements are concatenated, then hashed.
+struct ArraySerializer {
+    hasher: Sha256,
+}
 
-    // Serialize a single element of the sequence.
-    fn serialize_element<T>(&mut self, value: &T) -> Result<Self::Ok, Self::Error>
+impl Default for ArraySerializer {
+    fn default() -> Self {
+        ArraySerializer {
+            hasher: Sha256::new(),
+        }
+    }
+}
+
+impl ArraySerializer {
+    fn push<T>(&mut self, value: &T) -> Result<(), RequestIdError>
     where
         T: ?Sized + Serialize,
     {
-        let mut prev_encoder = self.element_encoder.take();
-
-        self.element_encoder = Some(Hasher::value());
-
-        value.serialize(&mut **self)?;
-
-        let value_encoder = self.element_encoder.take();
-        let hash = match value_encoder {
-            Some(Hasher::Value(hasher)) => Ok(hasher.finish()),
-            _ => Err(RequestIdError::InvalidState),
-        }?;
-
-        self.element_encoder = prev_encoder.take();
-        match &mut self.element_encoder {
-            Some(Hasher::Value(hasher)) => {
-                hasher.update(&hash);
-                Ok(())
-            }
-            _ => Err(RequestIdError::InvalidState),
-        }
+        self.hasher.update(&hash_present(value)?);
+        Ok(())
     }
 
-    // Close the sequence.
-    fn end(self) -> Result<Self::Ok, Self::Error> {
-        Ok(())
+    fn finish(self) -> Result<ValueHash, RequestIdError> {
+        Ok(Some(self.hasher.finish()))
     }
 }
 
-// Same thing but for tuples.
-impl<'a> ser::SerializeTuple for &'a mut RequestIdSerializer {
-    type Ok = ();
+impl ser::SerializeSeq for ArraySerializer {
+    type Ok = ValueHash;
     type Error = RequestIdError;
 
-    fn serialize_element<T>(&mut self, _value: &T) -> Result<Self::Ok, Self::Error>
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
         T: ?Sized + Serialize,
     {
-        Err(RequestIdError::UnsupportedTypeTuple)
+        self.push(value)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        Ok(())
+        self.finish()
     }
 }
 
-// Same thing but for tuple structs.
-impl<'a> ser::SerializeTupleStruct for &'a mut RequestIdSerializer {
-    type Ok = ();
+impl ser::SerializeTuple for ArraySerializer {
+    type Ok = ValueHash;
     type Error = RequestIdError;
 
-    fn serialize_field<T>(&mut self, _value: &T) -> Result<Self::Ok, Self::Error>
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
         T: ?Sized + Serialize,
     {
-        Err(RequestIdError::UnsupportedTypeTupleStruct)
+        self.push(value)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        Ok(())
+        self.finish()
     }
 }
 
-// Tuple variants are a little different. Refer back to the
-// `serialize_tuple_variant` method above:
-//
-//    self.output += "{";
-//    variant.serialize(&mut *self)?;
-//    self.output += ":[";
-//
-// So the `end` method in this impl is responsible for closing both the `]` and
-// the `}`.
-impl<'a> ser::SerializeTupleVariant for &'a mut RequestIdSerializer {
-    type Ok = ();
+impl ser::SerializeTupleStruct for ArraySerializer {
+    type Ok = ValueHash;
     type Error = RequestIdError;
 
-    fn serialize_field<T>(&mut self, _value: &T) -> Result<Self::Ok, Self::Error>
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
         T: ?Sized + Serialize,
     {
-        Err(RequestIdError::UnsupportedTypeTupleVariant)
+        self.push(value)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        Ok(())
+        self.finish()
+    }
+}
+
+/// Hashes a map or a structure: the hash of each key is concatenated with the hash of its
+/// value, then these concatenations are sorted, concatenated and hashed.
+#[derive(Default)]
+struct MapSerializer {
+    // We use a BTreeMap here as there is no indication that keys might not be duplicated,
+    // and we want to make sure they're overwritten in that case.
+    fields: BTreeMap<Sha256Hash, Sha256Hash>,
+    key: Option<Sha256Hash>,
+}
+
+impl MapSerializer {
+    fn insert(&mut self, key: Sha256Hash, value: ValueHash) {
+        match value {
+            Some(value) => self.fields.insert(key, value),
+            None => self.fields.remove(&key),
+        };
+    }
+
+    fn finish(self) -> Result<ValueHash, RequestIdError> {
+        let mut keyvalues: Vec<Vec<u8>> = self
+            .fields
+            .iter()
+            .map(|(k, v)| {
+                let mut x = k.to_vec();
+                x.extend(v);
+                x
+            })
+            .collect();
+        keyvalues.sort();
+
+        let mut hasher = Sha256::new();
+        for kv in keyvalues {
+            hasher.update(&kv);
+        }
+        Ok(Some(hasher.finish()))
     }
 }
 
-// Some `Serialize` types are not able to hold a key and value in memory at the
-// same time so `SerializeMap` implementations are required to support
-// `serialize_key` and `serialize_value` individually.
-//
-// There is a third optional method on the `SerializeMap` trait. The
-// `serialize_entry` method allows serializers to optimize for the case where
-// key and value are both available simultaneously. In JSON it doesn't make a
-// difference so the default behavior for `serialize_entry` is fine.
-impl<'a> ser::SerializeMap for &'a mut RequestIdSerializer {
-    type Ok = ();
+impl ser::SerializeMap for MapSerializer {
+    type Ok = ValueHash;
     type Error = RequestIdError;
 
-    // The Serde data model allows map keys to be any serializable type. JSON
-    // only allows string keys so the implementation below will produce invalid
-    // JSON if the key serializes as something other than a string.
-    //
-    // A real JSON serializer would need to validate that map keys are strings.
-    // This can be done by using a different Serializer to serialize the key
-    // (instead of `&mut **self`) and having that other serializer only
-    // implement `serialize_str` and return an error on any other data type.
-    fn serialize_key<T>(&mut self, _key: &T) -> Result<Self::Ok, Self::Error>
+    fn serialize_key<T>(&mut self, key: &T) -> Result<(), Self::Error>
     where
         T: ?Sized + Serialize,
     {
-        Err(RequestIdError::UnsupportedTypeMap)
+        self.key = Some(hash_present(key)?);
+        Ok(())
     }
 
-    // It doesn't make a difference whether the colon is printed at the end of
-    // `serialize_key` or at the beginning of `serialize_value`. In this case
-    // the code is a bit simpler having it here.
-    fn serialize_value<T>(&mut self, _value: &T) -> Result<Self::Ok, Self::Error>
+    fn serialize_value<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
         T: ?Sized + Serialize,
     {
-        Err(RequestIdError::UnsupportedTypeMap)
+        let key = self.key.take().ok_or(RequestIdError::InvalidState)?;
+        self.insert(key, value.serialize(RequestIdSerializer)?);
+        Ok(())
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        self.hash_fields()
+        self.finish()
     }
 }
 
 // Structs are like maps in which the keys are constrained to be compile-time
 // constant strings.
-impl<'a> ser::SerializeStruct for &'a mut RequestIdSerializer {
-    type Ok = ();
+impl ser::SerializeStruct for MapSerializer {
+    type Ok = ValueHash;
     type Error = RequestIdError;
 
-    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<Self::Ok, Self::Error>
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), Self::Error>
     where
         T: ?Sized + Serialize,
     {
-        let key_hash = self.hash_value(key)?;
-        let value_hash = self.hash_value(value)?;
-        match &mut self.element_encoder {
-            Some(Hasher::Struct { fields, .. }) => {
-                fields.insert(key_hash, value_hash);
-                Ok(())
-            }
-            _ => Err(RequestIdError::InvalidState),
-        }
+        self.insert(
+            hash_bytes(key.as_bytes()),
+            value.serialize(RequestIdSerializer)?,
+        );
+        Ok(())
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        self.hash_fields()
+        self.finish()
     }
 }
 
-// Similar to `SerializeTupleVariant`, here the `end` method is responsible for
-// closing both of the curly braces opened by `serialize_struct_variant`.
-impl<'a> ser::SerializeStructVariant for &'a mut RequestIdSerializer {
-    type Ok = ();
+/// Hashes a tuple or struct variant, as a map from the name of the variant to its content.
+struct VariantSerializer<S> {
+    variant: &'static str,
+    inner: S,
+}
+
+impl ser::SerializeTupleVariant for VariantSerializer<ArraySerializer> {
+    type Ok = ValueHash;
     type Error = RequestIdError;
 
-    fn serialize_field<T>(
-        &mut self,
-        _key: &'static str,
-        _value: &T,
-    ) -> Result<Self::Ok, Self::Error>
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
         T: ?Sized + Serialize,
     {
-        Err(RequestIdError::UnsupportedTypeStructVariant)
+        self.inner.push(value)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        Ok(())
+        hash_variant(self.variant, self.inner.finish()?)
+    }
+}
+
+impl ser::SerializeStructVariant for VariantSerializer<MapSerializer> {
+    type Ok = ValueHash;
+    type Error = RequestIdError;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), Self::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        ser::SerializeStruct::serialize_field(&mut self.inner, key, value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        hash_variant(self.variant, self.inner.finish()?)
     }
 }
 
@@ -679,16 +569,18 @@ impl<'a> ser::SerializeStructVariant for &'a mut RequestIdSerializer {
 /// envelope and should be included in the calculation of the request
 /// id.
 ///
-/// # Panics
+/// # Errors
 ///
-/// This function panics if the value provided is not a struct or a map.
+/// This function returns an error if the value (or anything nested in it) cannot be
+/// hashed, for example a float, or if the value itself is absent.
 pub fn to_request_id<'a, V>(value: &V) -> Result<RequestId, RequestIdError>
 where
     V: 'a + Serialize,
 {
-    let mut serializer = RequestIdSerializer::new();
-    value.serialize(&mut serializer)?;
-    serializer.finish()
+    value
+        .serialize(RequestIdSerializer)?
+        .map(RequestId)
+        .ok_or(RequestIdError::EmptySerializer)
 }
 
 #[cfg(test)]
@@ -852,20 +744,199 @@ mod tests {
         */
     }
 
-    /// We do not support creating a request id from a map.
-    /// It adds complexity, and isn't that useful anyway because a real request would
-    /// have to have different kinds of values (strings, principals, arrays) and
-    /// we don't support the wrappers that would be required to make that work
-    /// with rust maps.
+    /// Maps are hashed like structures with the same fields.
     #[test]
-    fn maps_are_not_supported() {
+    fn maps_are_hashed_like_structs() {
         let mut data = BTreeMap::new();
         data.insert("request_type", "call");
-        data.insert("canister_id", "a principal / the canister id");
         data.insert("method_name", "hello");
-        data.insert("arg", "some argument value");
 
-        let error = to_request_id(&data).unwrap_err();
-        assert_eq!(error, RequestIdError::UnsupportedTypeMap);
+        #[derive(Serialize)]
+        struct Struct {
+            request_type: &'static str,
+            method_name: &'static str,
+        }
+
+        assert_eq!(
+            to_request_id(&data).unwrap(),
+            to_request_id(&Struct {
+                request_type: "call",
+                method_name: "hello",
+            })
+            .unwrap()
+        );
+    }
+
+    // The expected hashes of the following tests were computed with an independent
+    // implementation of the representation-independent hash of the public spec, which
+    // also reproduces the hash of the `public_spec_example` above.
+
+    /// Maps nested in maps.
+    #[test]
+    fn nested_maps() {
+        #[derive(Serialize)]
+        struct Metadata {
+            version: i32,
+        }
+
+        #[derive(Serialize)]
+        struct Content {
+            method_name: &'static str,
+            #[serde(with = "serde_bytes")]
+            arg: Vec<u8>,
+            metadata: Metadata,
+        }
+
+        #[derive(Serialize)]
+        struct Request {
+            request_type: &'static str,
+            content: Content,
+        }
+
+        let data = Request {
+            request_type: "call",
+            content: Content {
+                method_name: "hello",
+                arg: b"DIDL\x00\xFD*".to_vec(),
+                metadata: Metadata { version: 2 },
+            },
+        };
+
+        let request_id = to_request_id(&data).unwrap();
+        assert_eq!(
+            hex::encode(request_id.0.to_vec()),
+            "d02b33097100410126b0cce5f9fc051e7cbcdab2961183a275f4363caedfeebc"
+        );
+    }
+
+    /// An array of maps, as in the `sender_delegation` field of an envelope.
+    #[test]
+    fn array_of_maps() {
+        use crate::identity::{Delegation, SignedDelegation};
+
+        #[derive(Serialize)]
+        struct Envelope {
+            sender_delegation: Vec<SignedDelegation>,
+        }
+
+        let data = Envelope {
+            sender_delegation: vec![SignedDelegation {
+                delegation: Delegation {
+                    pubkey: b"public key".to_vec(),
+                    expiration: 1_611_250_000_000_000_000,
+                    targets: Some(vec![Principal::try_from(&vec![
+                        0, 0, 0, 0, 0, 0, 0x04, 0xD2,
+                    ])
+                    .unwrap()]),
+                },
+                signature: b"signature".to_vec(),
+            }],
+        };
+
+        let request_id = to_request_id(&data).unwrap();
+        assert_eq!(
+            hex::encode(request_id.0.to_vec()),
+            "b0881f5a542de578ad335ae8bc2e3060c262d5f97d1037d284e09b730757711e"
+        );
+    }
+
+    /// Natural numbers are hashed as unsigned LEB128, integers as signed LEB128, and
+    /// booleans as 0 or 1.
+    #[test]
+    fn integers_and_booleans() {
+        #[derive(Serialize)]
+        struct Numbers {
+            zero: u8,
+            positive: u32,
+            max: u64,
+            negative: i32,
+            minus_one: i8,
+            yes: bool,
+            no: bool,
+        }
+
+        let data = Numbers {
+            zero: 0,
+            positive: 624_485,
+            max: u64::MAX,
+            negative: -123_456,
+            minus_one: -1,
+            yes: true,
+            no: false,
+        };
+
+        let request_id = to_request_id(&data).unwrap();
+        assert_eq!(
+            hex::encode(request_id.0.to_vec()),
+            "a01d521631d09d6564f8583f4a4c725be72ff7800c4c132a24534c80f61dae13"
+        );
+    }
+
+    /// Enum variants are hashed like serde_cbor represents them.
+    #[test]
+    fn enum_variants() {
+        #[derive(Serialize)]
+        enum Variant {
+            First,
+            Second(u8),
+            Third { a: &'static str },
+            Fourth(u8, u8),
+        }
+
+        #[derive(Serialize)]
+        struct Variants {
+            unit: Variant,
+            newtype: Variant,
+            r#struct: Variant,
+            tuple: Variant,
+        }
+
+        let data = Variants {
+            unit: Variant::First,
+            newtype: Variant::Second(42),
+            r#struct: Variant::Third { a: "b" },
+            tuple: Variant::Fourth(1, 2),
+        };
+
+        let request_id = to_request_id(&data).unwrap();
+        assert_eq!(
+            hex::encode(request_id.0.to_vec()),
+            "4bf9cf1a018f410662393a7e327875a9e23dd1deb6069e37b844a915b36a12df"
+        );
+    }
+
+    /// Fields that are `None` are left out.
+    #[test]
+    fn absent_fields() {
+        #[derive(Serialize)]
+        struct WithAbsent {
+            present: Option<&'static str>,
+            absent: Option<&'static str>,
+        }
+
+        let data = WithAbsent {
+            present: Some("value"),
+            absent: None,
+        };
+
+        let request_id = to_request_id(&data).unwrap();
+        assert_eq!(
+            hex::encode(request_id.0.to_vec()),
+            "bfdd5f2bc4fd9374ee9148745901f76011070e20a3309a09588cc1f90e720a1c"
+        );
+
+        let error = to_request_id(&vec![None::<&str>]).unwrap_err();
+        assert_eq!(error, RequestIdError::UnsupportedAbsentValue);
+    }
+
+    #[test]
+    fn floats_are_not_supported() {
+        #[derive(Serialize)]
+        struct WithFloat {
+            value: f64,
+        }
+
+        let error = to_request_id(&WithFloat { value: 1.0 }).unwrap_err();
+        assert_eq!(error, RequestIdError::UnsupportedTypeF64);
     }
 }
@@ -1,4 +1,4 @@
-use ic_agent::{Identity, Signature};
+use ic_agent::{AsyncIdentity, Identity, IdentityError, Signature};
 use ic_types::Principal;
 
 use openssl::sha::Sha256;
@@ -11,8 +11,11 @@ use pkcs11::types::{
 use pkcs11::Ctx;
 use simple_asn1::ASN1Block::{BitString, ObjectIdentifier, OctetString, Sequence};
 use simple_asn1::{from_der, oid, to_der, ASN1DecodeErr, ASN1EncodeErr, BigUint, OID};
+use std::future::Future;
 use std::path::Path;
+use std::pin::Pin;
 use std::ptr;
+use std::sync::{Arc, Mutex};
 use thiserror::Error;
 
 type KeyIdVec = Vec<u8>;
@@ -67,12 +70,21 @@ pub enum HardwareIdentityError {
 }
 
 /// An identity based on an HSM
+///
+/// As an [AsyncIdentity], it signs on the blocking thread pool of the tokio runtime, so
+/// that the executor is not blocked while the HSM signs.
 pub struct HardwareIdentity {
+    session: Arc<Mutex<Session>>,
+    public_key: DerPublicKeyVec,
+}
+
+// The session is shared with the blocking tasks that sign. PKCS#11 does not allow
+// overlapping operations on a session, so it is locked for the whole signature.
+struct Session {
     key_id: KeyIdVec,
     ctx: Ctx,
     session_handle: CK_SESSION_HANDLE,
     logged_in: bool,
-    public_key: DerPublicKeyVec,
 }
 
 impl HardwareIdentity {
@@ -99,26 +111,64 @@ impl HardwareIdentity {
         let public_key = get_der_encoded_public_key(&ctx, session_handle, &key_id)?;
 
         Ok(HardwareIdentity {
-            key_id,
-            ctx,
-            session_handle,
-            logged_in,
+            session: Arc::new(Mutex::new(Session {
+                key_id,
+                ctx,
+                session_handle,
+                logged_in,
+            })),
             public_key,
         })
     }
 }
 
 impl Identity for HardwareIdentity {
-    fn sender(&self) -> Result<Principal, String> {
+    fn sender(&self) -> Result<Principal, IdentityError> {
         Ok(Principal::self_authenticating(&self.public_key))
     }
-    fn sign(&self, msg: &[u8]) -> Result<Signature, String> {
+
+    fn public_key(&self) -> Option<Vec<u8>> {
+        Some(self.public_key.clone())
+    }
+    fn sign(&self, msg: &[u8]) -> Result<Signature, IdentityError> {
         let hash = hash_message(msg);
-        let signature = self.sign_hash(&hash)?;
+        let signature = sign_hash(&self.session, &hash)?;
 
         Ok(Signature {
             public_key: Some(self.public_key.clone()),
             signature: Some(signature),
+            delegations: None,
+        })
+    }
+}
+
+impl AsyncIdentity for HardwareIdentity {
+    fn sender(&self) -> Result<Principal, IdentityError> {
+        Identity::sender(self)
+    }
+
+    fn public_key(&self) -> Option<Vec<u8>> {
+        Identity::public_key(self)
+    }
+
+    fn sign<'a>(
+        &'a self,
+        msg: &'a [u8],
+    ) -> Pin<Box<dyn Future<Output = Result<Signature, IdentityError>> + Send + 'a>> {
+        let session = self.session.clone();
+        let hash = hash_message(msg);
+        Box::pin(async move {
+            let signature = tokio::task::spawn_blocking(move || sign_hash(&session, &hash))
+                .await
+                .map_err(|e| {
+                    IdentityError::SigningFailed(format!("Signing task failed: {}", e))
+                })??;
+
+            Ok(Signature {
+                public_key: Some(self.public_key.clone()),
+                signature: Some(signature),
+                delegations: None,
+            })
         })
     }
 }
@@ -338,11 +388,20 @@ fn hash_message(msg: &[u8]) -> Sha256Hash {
     sha256.finish()
 }
 
-impl HardwareIdentity {
-    fn sign_hash(&self, hash: &Sha256Hash) -> Result<Vec<u8>, String> {
+/// Sign a hash with the key of the session, holding the session until the signature is done.
+fn sign_hash(session: &Mutex<Session>, hash: &Sha256Hash) -> Result<Vec<u8>, IdentityError> {
+    session
+        .lock()
+        .map_err(|_| IdentityError::SigningFailed("The HSM session is poisoned.".to_string()))?
+        .sign_hash(hash)
+}
+
+impl Session {
+    fn sign_hash(&self, hash: &Sha256Hash) -> Result<Vec<u8>, IdentityError> {
         let private_key_handle =
-            get_private_key_handle(&self.ctx, self.session_handle, &self.key_id)
-                .map_err(|e| format!("Failed to get private key handle: {}", e))?;
+            get_private_key_handle(&self.ctx, self.session_handle, &self.key_id).map_err(|e| {
+                IdentityError::SigningFailed(format!("Failed to get private key handle: {}", e))
+            })?;
 
         let mechanism = CK_MECHANISM {
             mechanism: CKM_ECDSA,
@@ -351,14 +410,16 @@ impl HardwareIdentity {
         };
         self.ctx
             .sign_init(self.session_handle, &mechanism, private_key_handle)
-            .map_err(|e| format!("Failed to initialize signature: {}", e))?;
-        self.ctx
-            .sign(self.session_handle, hash)
-            .map_err(|e| format!("Failed to generate signature: {}", e))
+            .map_err(|e| {
+                IdentityError::SigningFailed(format!("Failed to initialize signature: {}", e))
+            })?;
+        self.ctx.sign(self.session_handle, hash).map_err(|e| {
+            IdentityError::SigningFailed(format!("Failed to generate signature: {}", e))
+        })
     }
 }
 
-impl Drop for HardwareIdentity {
+impl Drop for Session {
     fn drop(&mut self) {
         if self.logged_in {
             // necessary? probably not
@@ -1,12 +1,13 @@
 use async_trait::async_trait;
 use candid::de::ArgumentDecoder;
 use candid::{decode_args, decode_one};
-use delay::Waiter;
+use ic_agent::agent::PollingStrategy;
 use ic_agent::agent::UpdateBuilder;
 use ic_agent::export::Principal;
-use ic_agent::{Agent, AgentError, RequestId};
+use ic_agent::{Agent, AgentError, AsyncIdentity, RequestId};
 use serde::de::DeserializeOwned;
 use std::future::Future;
+use std::sync::Arc;
 
 mod expiry;
 pub use expiry::Expiry;
@@ -51,11 +52,11 @@ where
     /// Id does not have a type associated with it).
     async fn call(self) -> Result<RequestId, AgentError>;
 
-    /// Execute the call, and wait for an answer using a [Waiter] strategy. The return
+    /// Execute the call, and wait for an answer using a [PollingStrategy]. The return
     /// type is encoded in the trait.
     async fn call_and_wait<W>(self, mut waiter: W) -> Result<Out, AgentError>
     where
-        W: Waiter;
+        W: PollingStrategy;
 
     /// Apply a transformation function after the call has been successful. The transformation
     /// is applied with the result.
@@ -68,7 +69,7 @@ where
     /// use candid::{Encode, Decode, CandidType};
     ///
     /// # let canister_wasm = b"\0asm\x01\0\0\0";
-    /// # fn create_identity() -> impl ic_agent::Identity {
+    /// # fn create_identity() -> impl ic_agent::AsyncIdentity {
     /// #     let rng = ring::rand::SystemRandom::new();
     /// #     let key_pair = ring::signature::Ed25519KeyPair::generate_pkcs8(&rng)
     /// #         .expect("Could not generate a key pair.");
@@ -92,10 +93,8 @@ where
     ///     .with_interface(interfaces::ManagementCanister)
     ///     .build()?;
     ///
-    ///   let waiter = delay::Delay::builder()
-    ///     .throttle(std::time::Duration::from_millis(500))
-    ///     .timeout(std::time::Duration::from_secs(60 * 5))
-    ///     .build();
+    ///   let waiter = ic_agent::agent::Backoff::throttle(std::time::Duration::from_millis(500))
+    ///     .with_timeout(std::time::Duration::from_secs(60 * 5));
     ///
     ///   // Create a canister, then call the management canister to install a base canister
     ///   // WASM. This is to show how this API would be used, but is probably not a good
@@ -157,6 +156,7 @@ where
     pub(crate) method_name: String,
     pub(crate) arg: Result<Vec<u8>, AgentError>,
     pub(crate) expiry: Expiry,
+    pub(crate) identity: Option<Arc<dyn AsyncIdentity>>,
     pub(crate) phantom_out: std::marker::PhantomData<Out>,
 }
 
@@ -170,6 +170,9 @@ where
         self.expiry.apply_to_query(&mut builder);
         builder.with_arg(&self.arg?);
         builder.with_effective_canister_id(self.effective_canister_id);
+        if let Some(identity) = self.identity {
+            builder.with_identity(identity);
+        }
         builder.call().await
     }
 }
@@ -203,6 +206,7 @@ where
     pub(crate) method_name: String,
     pub(crate) arg: Result<Vec<u8>, AgentError>,
     pub(crate) expiry: Expiry,
+    pub(crate) identity: Option<Arc<dyn AsyncIdentity>>,
     pub(crate) phantom_out: std::marker::PhantomData<Out>,
 }
 
@@ -217,6 +221,9 @@ where
         self.expiry.apply_to_update(&mut builder);
         builder.with_arg(&self.arg?);
         builder.with_effective_canister_id(self.effective_canister_id);
+        if let Some(identity) = self.identity {
+            builder.with_identity(identity);
+        }
         Ok(builder)
     }
 
@@ -228,7 +235,7 @@ where
     ///
     pub async fn call_and_wait<W>(self, waiter: W) -> Result<Out, AgentError>
     where
-        W: Waiter,
+        W: PollingStrategy,
     {
         self.build_call()?
             .call_and_wait(waiter)
@@ -238,7 +245,7 @@ where
 
     pub async fn call_and_wait_one<W, T>(self, waiter: W) -> Result<T, AgentError>
     where
-        W: Waiter,
+        W: PollingStrategy,
         T: DeserializeOwned,
     {
         self.build_call()?
@@ -266,7 +273,7 @@ where
     }
     async fn call_and_wait<W>(self, waiter: W) -> Result<Out, AgentError>
     where
-        W: Waiter,
+        W: PollingStrategy,
     {
         self.call_and_wait(waiter).await
     }
@@ -310,7 +317,7 @@ where
     }
     pub async fn call_and_wait<W>(self, waiter: W) -> Result<Out2, AgentError>
     where
-        W: Waiter,
+        W: PollingStrategy,
     {
         let v = self.inner.call_and_wait(waiter).await?;
 
@@ -356,7 +363,7 @@ where
 
     async fn call_and_wait<W>(self, waiter: W) -> Result<Out2, AgentError>
     where
-        W: Waiter,
+        W: PollingStrategy,
     {
         self.call_and_wait(waiter).await
     }
@@ -397,7 +404,7 @@ where
     }
     pub async fn call_and_wait<W>(self, waiter: W) -> Result<Out2, AgentError>
     where
-        W: Waiter,
+        W: PollingStrategy,
     {
         let v = self.inner.call_and_wait(waiter).await?;
         Ok((self.map)(v))
@@ -438,7 +445,7 @@ where
 
     async fn call_and_wait<W>(self, waiter: W) -> Result<Out2, AgentError>
     where
-        W: Waiter,
+        W: PollingStrategy,
     {
         self.call_and_wait(waiter).await
     }
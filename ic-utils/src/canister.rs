@@ -3,9 +3,10 @@ use candid::de::ArgumentDecoder;
 use candid::parser::value::IDLValue;
 use candid::ser::IDLBuilder;
 use candid::CandidType;
-use ic_agent::{Agent, AgentError};
+use ic_agent::{Agent, AgentError, AsyncIdentity};
 use ic_types::{Principal, PrincipalError};
 use std::convert::TryInto;
+use std::sync::Arc;
 use thiserror::Error;
 
 /// An error happened while building a canister.
@@ -249,6 +250,7 @@ pub struct SyncCallBuilder<'agent, 'canister: 'agent, T> {
     method_name: String,
     effective_canister_id: Principal,
     arg: Argument,
+    identity: Option<Arc<dyn AsyncIdentity>>,
 }
 
 impl<'agent, 'canister: 'agent, T> SyncCallBuilder<'agent, 'canister, T> {
@@ -262,6 +264,7 @@ impl<'agent, 'canister: 'agent, T> SyncCallBuilder<'agent, 'canister, T> {
             method_name: method_name.into(),
             effective_canister_id: canister.canister_id_().to_owned(),
             arg: Default::default(),
+            identity: None,
         }
     }
 }
@@ -306,6 +309,15 @@ impl<'agent, 'canister: 'agent, Interface> SyncCallBuilder<'agent, 'canister, In
         self
     }
 
+    /// Sign this call with `identity` instead of the identity of the agent.
+    pub fn with_identity(
+        mut self,
+        identity: Arc<dyn AsyncIdentity>,
+    ) -> SyncCallBuilder<'agent, 'canister, Interface> {
+        self.identity = Some(identity);
+        self
+    }
+
     /// Builds an [SyncCaller] from this builder's state.
     pub fn build<Output>(self) -> SyncCaller<'canister, Output>
     where
@@ -319,6 +331,7 @@ impl<'agent, 'canister: 'agent, Interface> SyncCallBuilder<'agent, 'canister, In
             method_name: self.method_name.clone(),
             arg: self.arg.serialize(),
             expiry: Default::default(),
+            identity: self.identity,
             phantom_out: std::marker::PhantomData,
         }
     }
@@ -332,6 +345,7 @@ pub struct AsyncCallBuilder<'agent, 'canister: 'agent, T> {
     method_name: String,
     effective_canister_id: Principal,
     arg: Argument,
+    identity: Option<Arc<dyn AsyncIdentity>>,
 }
 
 impl<'agent, 'canister: 'agent, T> AsyncCallBuilder<'agent, 'canister, T> {
@@ -345,6 +359,7 @@ impl<'agent, 'canister: 'agent, T> AsyncCallBuilder<'agent, 'canister, T> {
             method_name: method_name.to_string(),
             effective_canister_id: canister.canister_id_().to_owned(),
             arg: Default::default(),
+            identity: None,
         }
     }
 }
@@ -378,6 +393,15 @@ impl<'agent, 'canister: 'agent, Interface> AsyncCallBuilder<'agent, 'canister, I
         self
     }
 
+    /// Sign this call with `identity` instead of the identity of the agent.
+    pub fn with_identity(
+        mut self,
+        identity: Arc<dyn AsyncIdentity>,
+    ) -> AsyncCallBuilder<'agent, 'canister, Interface> {
+        self.identity = Some(identity);
+        self
+    }
+
     /// Builds an [AsyncCaller] from this builder's state.
     pub fn build<Output>(self) -> AsyncCaller<'canister, Output>
     where
@@ -391,6 +415,7 @@ impl<'agent, 'canister: 'agent, Interface> AsyncCallBuilder<'agent, 'canister, I
             method_name: self.method_name.clone(),
             arg: self.arg.serialize(),
             expiry: Default::default(),
+            identity: self.identity,
             phantom_out: std::marker::PhantomData,
         }
     }
@@ -406,7 +431,7 @@ mod tests {
     #[tokio::test]
     async fn simple() {
         use super::Canister;
-        use delay::Delay;
+        use ic_agent::agent::Backoff;
 
         let rng = ring::rand::SystemRandom::new();
         let key_pair = ring::signature::Ed25519KeyPair::generate_pkcs8(&rng)
@@ -433,13 +458,13 @@ mod tests {
 
         let (new_canister_id,) = management_canister
             .create_canister()
-            .call_and_wait(Delay::throttle(std::time::Duration::from_secs(1)))
+            .call_and_wait(Backoff::throttle(std::time::Duration::from_secs(1)))
             .await
             .unwrap();
 
         let (status,) = management_canister
             .canister_status(&new_canister_id)
-            .call_and_wait(Delay::throttle(std::time::Duration::from_secs(1)))
+            .call_and_wait(Backoff::throttle(std::time::Duration::from_secs(1)))
             .await
             .unwrap();
 
@@ -448,7 +473,7 @@ mod tests {
         let canister_wasm = b"\0asm\x01\0\0\0";
         management_canister
             .install_code(&new_canister_id, canister_wasm)
-            .call_and_wait(Delay::throttle(std::time::Duration::from_secs(1)))
+            .call_and_wait(Backoff::throttle(std::time::Duration::from_secs(1)))
             .await
             .unwrap();
 
@@ -461,7 +486,7 @@ mod tests {
         assert!(canister
             .update_("hello")
             .build::<()>()
-            .call_and_wait(Delay::throttle(std::time::Duration::from_secs(1)))
+            .call_and_wait(Backoff::throttle(std::time::Duration::from_secs(1)))
             .await
             .is_err());
     }
@@ -3,7 +3,7 @@ use crate::canister::{Argument, CanisterBuilder};
 use crate::Canister;
 use async_trait::async_trait;
 use candid::{CandidType, Deserialize};
-use delay::Waiter;
+use ic_agent::agent::PollingStrategy;
 use ic_agent::export::Principal;
 use ic_agent::{Agent, AgentError, RequestId};
 use std::convert::AsRef;
@@ -269,7 +269,7 @@ impl<'agent, 'canister: 'agent, T> InstallCodeBuilder<'agent, 'canister, T> {
     /// Make a call. This is equivalent to the [AsyncCall::call_and_wait].
     pub async fn call_and_wait<W>(self, waiter: W) -> Result<(), AgentError>
     where
-        W: Waiter,
+        W: PollingStrategy,
     {
         self.build()?.call_and_wait(waiter).await
     }
@@ -285,7 +285,7 @@ impl<'agent, 'canister: 'agent, T: Sync> AsyncCall<()>
 
     async fn call_and_wait<W>(self, waiter: W) -> Result<(), AgentError>
     where
-        W: Waiter,
+        W: PollingStrategy,
     {
         self.build()?.call_and_wait(waiter).await
     }
@@ -4,7 +4,7 @@ use crate::Canister;
 use async_trait::async_trait;
 use candid::de::ArgumentDecoder;
 use candid::{decode_args, CandidType, Deserialize};
-use delay::Waiter;
+use ic_agent::agent::PollingStrategy;
 use ic_agent::agent::UpdateBuilder;
 use ic_agent::export::Principal;
 use ic_agent::{Agent, AgentError, RequestId};
@@ -75,7 +75,7 @@ where
 
     pub async fn call_and_wait<W>(self, waiter: W) -> Result<Out, AgentError>
     where
-        W: Waiter,
+        W: PollingStrategy,
     {
         self.build()?.call_and_wait(waiter).await
     }
@@ -92,7 +92,7 @@ where
 
     async fn call_and_wait<W>(self, waiter: W) -> Result<Out, AgentError>
     where
-        W: Waiter,
+        W: PollingStrategy,
     {
         self.call_and_wait(waiter).await
     }
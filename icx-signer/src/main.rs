@@ -0,0 +1,155 @@
+use anyhow::{anyhow, bail, Context};
+use clap::{crate_authors, crate_version, AppSettings, Clap};
+use ic_agent::identity::{BasicIdentity, Secp256k1Identity, SignerServer};
+use ic_identity_hsm::HardwareIdentity;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The environment variable holding the password of encrypted PEM files.
+const PASSWORD_ENV: &str = "ICX_SIGNER_PASSWORD";
+
+/// The environment variable holding the PIN of the HSM.
+const HSM_PIN_ENV: &str = "ICX_SIGNER_HSM_PIN";
+
+#[derive(Clap)]
+#[clap(
+    version = crate_version!(),
+    author = crate_authors!(),
+    global_setting = AppSettings::GlobalVersion,
+    global_setting = AppSettings::ColoredHelp
+)]
+struct Opts {
+    /// The path of the Unix domain socket to listen on. It must not exist yet, and is only
+    /// accessible to the current user.
+    #[clap(long)]
+    socket: PathBuf,
+
+    /// Serve an Ed25519 key read from a PEM file, as `NAME=PATH`. Encrypted files are
+    /// decrypted with the password in the ICX_SIGNER_PASSWORD environment variable.
+    #[clap(long = "basic-pem", number_of_values = 1)]
+    basic_pems: Vec<String>,
+
+    /// Serve a secp256k1 key read from a PEM file, as `NAME=PATH`. Encrypted files are
+    /// decrypted with the password in the ICX_SIGNER_PASSWORD environment variable.
+    #[clap(long = "secp256k1-pem", number_of_values = 1)]
+    secp256k1_pems: Vec<String>,
+
+    /// Serve a key held by an HSM, as `NAME=PKCS11_LIBRARY_PATH:SLOT_INDEX:KEY_ID`. The PIN
+    /// of the HSM is read from the ICX_SIGNER_HSM_PIN environment variable.
+    #[clap(long = "hsm", number_of_values = 1)]
+    hsms: Vec<String>,
+}
+
+/// Split an argument of the form `NAME=VALUE`.
+fn split_named(arg: &str) -> anyhow::Result<(&str, &str)> {
+    let mut parts = arg.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(name), Some(value)) if !name.is_empty() => Ok((name, value)),
+        _ => bail!(r#"Expected "NAME=VALUE", found "{}"."#, arg),
+    }
+}
+
+fn env_var(name: &'static str) -> impl FnOnce() -> Result<String, String> {
+    move || std::env::var(name).map_err(|_| format!("The {} variable is not set.", name))
+}
+
+fn create_server(opts: &Opts) -> anyhow::Result<SignerServer> {
+    let mut server = SignerServer::new();
+
+    for arg in &opts.basic_pems {
+        let (name, path) = split_named(arg)?;
+        let identity = BasicIdentity::from_encrypted_pem_file(path, env_var(PASSWORD_ENV))
+            .with_context(|| format!("Cannot read the key {} from {}.", name, path))?;
+        server.add_key(name, identity);
+    }
+
+    for arg in &opts.secp256k1_pems {
+        let (name, path) = split_named(arg)?;
+        let identity = Secp256k1Identity::from_encrypted_pem_file(path, env_var(PASSWORD_ENV))
+            .with_context(|| format!("Cannot read the key {} from {}.", name, path))?;
+        server.add_key(name, identity);
+    }
+
+    for arg in &opts.hsms {
+        let (name, hsm) = split_named(arg)?;
+        let parts: Vec<&str> = hsm.rsplitn(3, ':').collect();
+        let (library_path, slot_index, key_id) = match parts.as_slice() {
+            [key_id, slot_index, library_path] => (library_path, slot_index, key_id),
+            _ => bail!(
+                r#"Expected "NAME=PKCS11_LIBRARY_PATH:SLOT_INDEX:KEY_ID", found "{}"."#,
+                arg
+            ),
+        };
+        let slot_index = slot_index
+            .parse::<usize>()
+            .with_context(|| format!("Invalid slot index {}.", slot_index))?;
+        let identity =
+            HardwareIdentity::new(library_path, slot_index, key_id, env_var(HSM_PIN_ENV))
+                .with_context(|| format!("Cannot open the key {} in the HSM.", name))?;
+        server.add_key(name, identity);
+    }
+
+    Ok(server)
+}
+
+/// Listen on a socket only the current user can connect to.
+///
+/// The socket is bound in a new directory only the current user can open, and linked to
+/// `socket` once its own permissions are restricted, so no other user can connect to it
+/// in between. Linking fails if `socket` already exists.
+fn bind_private(socket: &Path) -> anyhow::Result<UnixListener> {
+    let file_name = socket
+        .file_name()
+        .ok_or_else(|| anyhow!("{} is not a file path.", socket.display()))?;
+    let dir = socket.with_file_name(format!(
+        ".{}.{}",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+    std::fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&dir)
+        .with_context(|| format!("Cannot create {}.", dir.display()))?;
+
+    let private_socket = dir.join(file_name);
+    let listener = UnixListener::bind(&private_socket)
+        .and_then(|listener| {
+            std::fs::set_permissions(&private_socket, std::fs::Permissions::from_mode(0o600))?;
+            std::fs::hard_link(&private_socket, socket)?;
+            Ok(listener)
+        })
+        .with_context(|| format!("Cannot listen on {}.", socket.display()));
+    let _ = std::fs::remove_dir_all(&dir);
+    listener
+}
+
+fn main() -> anyhow::Result<()> {
+    let opts: Opts = Opts::parse();
+    if opts.basic_pems.is_empty() && opts.secp256k1_pems.is_empty() && opts.hsms.is_empty() {
+        return Err(anyhow!("No key to serve."));
+    }
+    let server = Arc::new(create_server(&opts)?);
+
+    let listener = bind_private(&opts.socket)?;
+    eprintln!("Listening on {}", opts.socket.display());
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Cannot accept a connection: {}", e);
+                continue;
+            }
+        };
+        let server = server.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = server.serve_connection(stream) {
+                eprintln!("Connection closed: {}", e);
+            }
+        });
+    }
+
+    Ok(())
+}
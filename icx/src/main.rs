@@ -4,21 +4,20 @@ use candid::{check_prog, IDLArgs, IDLProg, TypeEnv};
 use candid::{CandidType, Decode, Deserialize};
 use clap::{crate_authors, crate_version, AppSettings, Clap};
 use ic_agent::agent::agent_error::HttpErrorPayload;
-use ic_agent::agent::http_transport::ReqwestHttpReplicaV2Transport;
-use ic_agent::agent::ReplicaV2Transport;
+use ic_agent::agent::{
+    Backoff, PollingStrategy, RejectCode, Replied, RequestStatusResponse, SignedQuery,
+    SignedRequestStatus, SignedUpdate,
+};
 use ic_agent::export::Principal;
 use ic_agent::identity::BasicIdentity;
-use ic_agent::{agent, Agent, AgentError, Identity, RequestId};
+use ic_agent::{agent, Agent, AgentError, Identity};
 use ic_utils::interfaces::management_canister::{CanisterInstall, MgmtMethod};
-use ring::signature::Ed25519KeyPair;
-use std::collections::VecDeque;
+use serde::Serialize;
 use std::convert::TryFrom;
 use std::future::Future;
 use std::io::BufRead;
 use std::path::PathBuf;
 use std::pin::Pin;
-use std::str::FromStr;
-use thiserror::Error;
 
 #[derive(Clap)]
 #[clap(
@@ -28,7 +27,8 @@ use thiserror::Error;
     global_setting = AppSettings::ColoredHelp
 )]
 struct Opts {
-    /// Some input. Because this isn't an Option<T> it's required to be used
+    /// The URL of the replica. A URL of the form `unix:///path/to/socket` talks to a
+    /// replica listening on a Unix domain socket.
     #[clap(default_value = "http://localhost:8000/")]
     replica: String,
 
@@ -37,11 +37,21 @@ struct Opts {
     #[clap(long)]
     pem: Option<PathBuf>,
 
+    /// Save the random identity created when no PEM file is passed to this path, so it
+    /// can be passed to `--pem` later.
+    #[clap(long, conflicts_with = "pem")]
+    save_pem: Option<PathBuf>,
+
     /// An optional field to set the expiry time on requests. Can be a human
     /// readable time (like `100s`) or a number of seconds.
     #[clap(long)]
     ttl: Option<humantime::Duration>,
 
+    /// Trust the root key returned by the replica's status endpoint, even if the replica
+    /// is not local. Only use this against test networks.
+    #[clap(long)]
+    fetch_root_key: bool,
+
     #[clap(subcommand)]
     subcommand: SubCommand,
 }
@@ -57,7 +67,8 @@ enum SubCommand {
     /// Checks the `status` endpoints of the replica.
     Status,
 
-    /// Send a serialized request, taking from STDIN.
+    /// Send requests signed with `--serialize`, taking them from STDIN, and wait for the
+    /// result of update calls.
     Send,
 
     /// Transform Principal from hex to new text.
@@ -71,7 +82,8 @@ struct CallOpts {
     #[clap(parse(try_from_str), required = true)]
     canister_id: Principal,
 
-    /// Output the serialization of a message to STDOUT.
+    /// Sign the request without sending it, and output it to STDOUT so it can be sent
+    /// later with `icx send`. Update calls are followed by a request for their status.
     #[clap(long)]
     serialize: bool,
 
@@ -273,127 +285,134 @@ pub fn get_effective_canister_id(
     }
 }
 
-fn create_identity(maybe_pem: Option<PathBuf>) -> impl Identity {
+fn create_identity(maybe_pem: Option<PathBuf>, save_pem: Option<PathBuf>) -> impl Identity {
     if let Some(pem_path) = maybe_pem {
         BasicIdentity::from_pem_file(pem_path).expect("Could not read the key pair.")
     } else {
-        let rng = ring::rand::SystemRandom::new();
-        let pkcs8_bytes = ring::signature::Ed25519KeyPair::generate_pkcs8(&rng)
-            .expect("Could not generate a key pair.")
-            .as_ref()
-            .to_vec();
-
-        BasicIdentity::from_key_pair(
-            Ed25519KeyPair::from_pkcs8(&pkcs8_bytes).expect("Could not generate the key pair."),
-        )
+        let identity = BasicIdentity::generate().expect("Could not generate a key pair.");
+        if let Some(save_path) = save_pem {
+            let pem = identity.to_pem().expect("Could not export the key pair.");
+            write_private_file(&save_path, pem.as_bytes()).expect("Could not save the key pair.");
+        }
+        identity
     }
 }
 
-#[derive(Error, Debug)]
-enum SerializeError {
-    #[error("")]
-    Success,
+/// Write a file only readable by the current user, as it holds a private key.
+fn write_private_file(path: &std::path::Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(contents)
 }
 
-struct SerializingTransport;
-
-impl agent::ReplicaV2Transport for SerializingTransport {
-    fn query<'a>(
-        &'a self,
-        effective_canister_id: Principal,
-        envelope: Vec<u8>,
-    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, AgentError>> + Send + 'a>> {
-        async fn run(
-            _: &SerializingTransport,
-            effective_canister_id: Principal,
-            envelope: Vec<u8>,
-        ) -> Result<Vec<u8>, AgentError> {
-            print!(
-                "query\n\n{}\n\n{}",
-                hex::encode(envelope),
-                hex::encode(effective_canister_id)
-            );
-            Err(AgentError::TransportError(SerializeError::Success.into()))
-        }
+/// A backoff printing a dot every time it waits, to show progress to the user.
+struct ProgressBackoff(Backoff);
 
-        Box::pin(run(self, effective_canister_id, envelope))
+impl PollingStrategy for ProgressBackoff {
+    fn start(&mut self) {
+        self.0.start()
     }
 
-    fn read_state<'a>(
-        &'a self,
-        effective_canister_id: Principal,
-        envelope: Vec<u8>,
-    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, AgentError>> + Send + 'a>> {
-        async fn run(
-            _: &SerializingTransport,
-            effective_canister_id: Principal,
-            envelope: Vec<u8>,
-        ) -> Result<Vec<u8>, AgentError> {
-            print!(
-                "read_state\n\n{}\n\n{}",
-                hex::encode(envelope),
-                hex::encode(effective_canister_id)
-            );
-            Err(AgentError::TransportError(SerializeError::Success.into()))
-        }
+    fn restart(&mut self) -> Result<(), AgentError> {
+        self.0.restart()
+    }
 
-        Box::pin(run(self, effective_canister_id, envelope))
+    fn wait<'a>(&'a mut self) -> Pin<Box<dyn Future<Output = Result<(), AgentError>> + Send + 'a>> {
+        eprint!(".");
+        self.0.wait()
     }
+}
 
-    fn call<'a>(
-        &'a self,
-        effective_canister_id: Principal,
-        envelope: Vec<u8>,
-        request_id: RequestId,
-    ) -> Pin<Box<dyn Future<Output = Result<(), AgentError>> + Send + 'a>> {
-        async fn run(
-            _: &SerializingTransport,
-            effective_canister_id: Principal,
-            envelope: Vec<u8>,
-            request_id: RequestId,
-        ) -> Result<(), AgentError> {
-            print!(
-                "call\n{}\n\n{}\n\n{}",
-                hex::encode(request_id.as_slice()),
-                hex::encode(envelope),
-                hex::encode(effective_canister_id)
-            );
-            Err(AgentError::MessageError(String::new()))
-        }
+/// A request signed by `icx update --serialize` or `icx query --serialize`, to be submitted
+/// by `icx send`. Each request is written as a line of hex-encoded CBOR.
+#[derive(Serialize, Deserialize)]
+enum SignedMessage {
+    Update(SignedUpdate),
+    RequestStatus(SignedRequestStatus),
+    Query(SignedQuery),
+}
 
-        Box::pin(run(self, effective_canister_id, envelope, request_id))
+impl SignedMessage {
+    fn to_line(&self) -> Result<String, serde_cbor::Error> {
+        Ok(hex::encode(serde_cbor::to_vec(self)?))
     }
 
-    fn status<'a>(
-        &'a self,
-    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, AgentError>> + Send + 'a>> {
-        async fn run(_: &SerializingTransport) -> Result<Vec<u8>, AgentError> {
-            Err(AgentError::MessageError(
-                "status calls not supported".to_string(),
-            ))
+    fn from_line(line: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(serde_cbor::from_slice(&hex::decode(line.trim())?)?)
+    }
+}
+
+/// Submit a request status until the update call it is about either replied or failed.
+async fn wait_for_request_status(
+    agent: &Agent,
+    signed: &SignedRequestStatus,
+) -> Result<Vec<u8>, AgentError> {
+    let mut waiter = ProgressBackoff(
+        Backoff::exponential(std::time::Duration::from_secs(1), 1.1)
+            .with_timeout(std::time::Duration::from_secs(60 * 5)),
+    );
+    waiter.start();
+    loop {
+        match agent.submit_request_status(signed).await? {
+            RequestStatusResponse::Replied {
+                reply: Replied::CallReplied(arg),
+            } => return Ok(arg),
+            RequestStatusResponse::Rejected {
+                reject_code,
+                reject_message,
+            } => {
+                return Err(AgentError::ReplicaError {
+                    reject_code,
+                    reject_message,
+                })
+            }
+            RequestStatusResponse::Done => {
+                return Err(AgentError::RequestStatusDoneNoReply(String::from(
+                    signed.request_id,
+                )))
+            }
+            RequestStatusResponse::Unknown
+            | RequestStatusResponse::Received
+            | RequestStatusResponse::Processing => {}
         }
+        waiter.wait().await?;
+    }
+}
 
-        Box::pin(run(self))
+/// Create the transport to the replica, picked from the scheme of its URL.
+fn create_transport(
+    url: &str,
+) -> Result<Box<dyn agent::ReplicaV2Transport + Send + Sync>, AgentError> {
+    #[cfg(unix)]
+    {
+        use agent::unix_socket_transport::{UnixSocketReplicaV2Transport, UNIX_SOCKET_SCHEME};
+
+        if url.starts_with(&format!("{}:", UNIX_SOCKET_SCHEME)) {
+            return Ok(Box::new(UnixSocketReplicaV2Transport::from_url(url)?));
+        }
     }
+
+    Ok(Box::new(
+        agent::http_transport::ReqwestHttpReplicaV2Transport::create(url)?,
+    ))
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let opts: Opts = Opts::parse();
 
-    let agent = match &opts.subcommand {
-        SubCommand::Query(CallOpts {
-            serialize: true, ..
-        })
-        | SubCommand::Update(CallOpts {
-            serialize: true, ..
-        }) => Agent::builder().with_transport(SerializingTransport),
-        _ => Agent::builder().with_transport(
-            agent::http_transport::ReqwestHttpReplicaV2Transport::create(opts.replica.clone())?,
-        ),
-    }
-    .with_boxed_identity(Box::new(create_identity(opts.pem)))
-    .build()?;
+    let agent = Agent::builder()
+        .with_boxed_transport(create_transport(&opts.replica)?)
+        .with_boxed_identity(Box::new(create_identity(opts.pem, opts.save_pem)))
+        .with_allow_fetch_root_key(opts.fetch_root_key)
+        .build()?;
 
     // You can handle information about subcommands by requesting their matches by name
     // (as below), requesting just the name used, or both at the same time
@@ -414,10 +433,58 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 t.canister_id.clone(),
             )?;
 
+            if t.serialize {
+                let messages = match &opts.subcommand {
+                    SubCommand::Update(_) => {
+                        let mut builder = agent.update(&t.canister_id, &t.method_name);
+                        if let Some(d) = expire_after {
+                            builder.expire_after(d);
+                        }
+                        let signed_update = builder
+                            .with_arg(arg)
+                            .with_effective_canister_id(effective_canister_id.clone())
+                            .sign()
+                            .await?;
+                        let signed_request_status = agent
+                            .sign_request_status(
+                                signed_update.request_id,
+                                effective_canister_id,
+                                Some(signed_update.ingress_expiry),
+                            )
+                            .await?;
+                        vec![
+                            SignedMessage::Update(signed_update),
+                            SignedMessage::RequestStatus(signed_request_status),
+                        ]
+                    }
+                    SubCommand::Query(_) => {
+                        let mut builder = agent.query(&t.canister_id, &t.method_name);
+                        if let Some(d) = expire_after {
+                            builder.expire_after(d);
+                        }
+                        let signed_query = builder
+                            .with_arg(arg)
+                            .with_effective_canister_id(effective_canister_id)
+                            .sign()
+                            .await?;
+                        vec![SignedMessage::Query(signed_query)]
+                    }
+                    _ => unreachable!(),
+                };
+                for message in messages {
+                    println!("{}", message.to_line()?);
+                }
+                return Ok(());
+            }
+
             let result = match &opts.subcommand {
                 SubCommand::Update(_) => {
-                    // We need to fetch the root key for updates.
-                    agent.fetch_root_key().await?;
+                    // We need to fetch the root key for updates, unless we talk to a
+                    // remote replica which uses the pinned mainnet root key.
+                    match agent.fetch_root_key().await {
+                        Err(AgentError::RootKeyFetchNotAllowed()) => {}
+                        result => result?,
+                    }
 
                     let mut builder = agent.update(&t.canister_id, &t.method_name);
 
@@ -429,16 +496,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     let result = builder
                         .with_arg(arg)
                         .with_effective_canister_id(effective_canister_id)
-                        .call_and_wait(
-                            delay::Delay::builder()
-                                .exponential_backoff(std::time::Duration::from_secs(1), 1.1)
-                                .side_effect(|| {
-                                    eprint!(".");
-                                    Ok(())
-                                })
-                                .timeout(std::time::Duration::from_secs(60 * 5))
-                                .build(),
-                        )
+                        .call_and_wait(ProgressBackoff(
+                            Backoff::exponential(std::time::Duration::from_secs(1), 1.1)
+                                .with_timeout(std::time::Duration::from_secs(60 * 5)),
+                        ))
                         .await;
                     eprintln!();
                     result
@@ -463,7 +524,6 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     print_idl_blob(&blob, &t.output, &method_type)
                         .map_err(|e| format!("Invalid IDL blob: {}", e))?;
                 }
-                Err(AgentError::TransportError(_)) => return Ok(()),
                 Err(AgentError::HttpError(HttpErrorPayload {
                     status,
                     content_type,
@@ -482,7 +542,23 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         }
                     }
                 }
-                Err(s) => eprintln!("Error: {:?}", s),
+                Err(AgentError::ReplicaError {
+                    reject_code,
+                    reject_message,
+                }) => {
+                    eprintln!("The replica rejected the call:");
+                    eprintln!("  Code:    {}", reject_code);
+                    eprintln!("  Message: {}", reject_message);
+                    if reject_code == RejectCode::SysTransient {
+                        eprintln!("This error is transient, the call can be retried.");
+                    }
+                }
+                Err(s) => {
+                    eprintln!("Error: {:?}", s);
+                    if s.is_retryable() {
+                        eprintln!("This error might be transient, the call can be retried.");
+                    }
+                }
             }
         }
         SubCommand::Status => println!("{:#}", agent.status().await?),
@@ -498,61 +574,46 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
         }
         SubCommand::Send => {
-            let mut input: VecDeque<String> = std::io::stdin()
+            let input = std::io::stdin()
                 .lock()
                 .lines()
-                .collect::<Result<VecDeque<String>, std::io::Error>>()?;
-            let mut line = input.pop_front().unwrap();
-            while line == "" {
-                line = input.pop_front().unwrap();
+                .collect::<Result<Vec<String>, std::io::Error>>()?;
+            let messages = input
+                .iter()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| SignedMessage::from_line(line))
+                .collect::<Result<Vec<SignedMessage>, _>>()?;
+
+            // Verifying the status of update calls requires the root key of local replicas.
+            if messages
+                .iter()
+                .any(|message| matches!(message, SignedMessage::RequestStatus(_)))
+            {
+                match agent.fetch_root_key().await {
+                    Err(AgentError::RootKeyFetchNotAllowed()) => {}
+                    result => result?,
+                }
             }
 
-            let transport = ReqwestHttpReplicaV2Transport::create(opts.replica)?;
-            match line.as_str() {
-                "call" => {
-                    line = input.pop_front().unwrap(); // request id.
-                    let request_id = RequestId::from_str(&line)?;
-                    input.pop_front().unwrap(); // empty line.
-                    line = input.pop_front().unwrap(); // envelope
-                    let envelope = hex::decode(line)?;
-                    input.pop_front().unwrap(); // empty line.
-                    line = input.pop_front().unwrap(); // effective_canister_id
-                    let effective_canister_id = Principal::try_from(hex::decode(line)?)?;
-                    transport
-                        .call(effective_canister_id, envelope, request_id)
-                        .await?;
-                    eprint!("Request ID: ");
-                    println!("0x{}", hex::encode(request_id.as_slice()));
-                }
-                "read_state" => {
-                    input.pop_front().unwrap(); // empty line.
-                    line = input.pop_front().unwrap(); // envelope
-                    let envelope = hex::decode(line)?;
-                    input.pop_front().unwrap(); // empty line.
-                    line = input.pop_front().unwrap(); // effective_canister_id
-                    let effective_canister_id = Principal::try_from(hex::decode(line)?)?;
-                    let result = transport
-                        .read_state(effective_canister_id, envelope)
-                        .await?;
-                    eprint!("Result: ");
-                    println!("{}", hex::encode(result));
-                }
-                "query" => {
-                    input.pop_front().unwrap(); // empty line.
-                    line = input.pop_front().unwrap(); // envelope
-                    let envelope = hex::decode(line)?;
-                    input.pop_front().unwrap(); // empty line.
-                    line = input.pop_front().unwrap(); // effective_canister_id
-                    let effective_canister_id = Principal::try_from(hex::decode(line)?)?;
-                    let result = transport.query(effective_canister_id, envelope).await?;
-                    eprint!("Result: ");
-                    println!("{}", hex::encode(result));
-                }
-                other => {
-                    eprintln!(
-                        r#"Error: Invalid STDIN format. Unexpected line: "{}""#,
-                        other
-                    );
+            for message in messages {
+                match message {
+                    SignedMessage::Update(signed) => {
+                        let request_id = agent.submit_update(&signed).await?;
+                        eprint!("Request ID: ");
+                        println!("0x{}", String::from(request_id));
+                    }
+                    SignedMessage::RequestStatus(signed) => {
+                        eprint!(".");
+                        let result = wait_for_request_status(&agent, &signed).await;
+                        eprintln!();
+                        print_idl_blob(&result?, &ArgType::Idl, &None)
+                            .map_err(|e| format!("Invalid IDL blob: {}", e))?;
+                    }
+                    SignedMessage::Query(signed) => {
+                        let result = agent.submit_query(&signed).await?;
+                        print_idl_blob(&result, &ArgType::Idl, &None)
+                            .map_err(|e| format!("Invalid IDL blob: {}", e))?;
+                    }
                 }
             }
         }
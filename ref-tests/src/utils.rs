@@ -1,4 +1,4 @@
-use delay::Delay;
+use ic_agent::agent::Backoff;
 use ic_agent::export::Principal;
 use ic_agent::identity::BasicIdentity;
 use ic_agent::{Agent, Identity};
@@ -17,10 +17,8 @@ const HSM_SLOT_INDEX: &str = "HSM_SLOT_INDEX";
 const HSM_KEY_ID: &str = "HSM_KEY_ID";
 const HSM_PIN: &str = "HSM_PIN";
 
-pub fn create_waiter() -> Delay {
-    Delay::builder()
-        .throttle(std::time::Duration::from_secs(5))
-        .build()
+pub fn create_waiter() -> Backoff {
+    Backoff::throttle(std::time::Duration::from_secs(5))
 }
 
 pub async fn create_identity() -> Result<Box<dyn Identity + Send + Sync>, String> {
@@ -37,6 +37,7 @@ fn spec_compliance_claimed() {
 }
 
 mod management_canister {
+    use ic_agent::agent::RejectCode;
     use ic_agent::export::Principal;
     use ic_agent::AgentError;
     use ic_utils::call::AsyncCall;
@@ -289,7 +290,7 @@ mod management_canister {
                 .call()
                 .await;
             assert!(matches!(result, Err(AgentError::ReplicaError {
-                    reject_code: 5,
+                    reject_code: RejectCode::CanisterError,
                     reject_message,
                 }) if reject_message == "canister is stopped"));
 
@@ -311,7 +312,7 @@ mod management_canister {
                 .call_and_wait(create_waiter())
                 .await;
             assert!(matches!(result, Err(AgentError::ReplicaError {
-                    reject_code: 3,
+                    reject_code: RejectCode::DestinationInvalid,
                     reject_message,
                 }) if reject_message == "method does not exist: update"));
 
@@ -322,7 +323,7 @@ mod management_canister {
                 .call()
                 .await;
             assert!(matches!(result, Err(AgentError::ReplicaError {
-                    reject_code: 3,
+                    reject_code: RejectCode::DestinationInvalid,
                     reject_message,
                 }) if reject_message == "query method does not exist"));
 
@@ -364,7 +365,7 @@ mod management_canister {
                 .call()
                 .await;
             assert!(matches!(result, Err(AgentError::ReplicaError {
-                    reject_code: 3,
+                    reject_code: RejectCode::DestinationInvalid,
                     reject_message,
                 }) if reject_message
                     == format!("canister no longer exists: {}", canister_id.to_text())));
@@ -558,6 +559,7 @@ mod management_canister {
 
 mod simple_calls {
     use crate::universal_canister::payload;
+    use ic_agent::agent::RejectCode;
     use ic_agent::AgentError;
     use ref_tests::{create_waiter, with_universal_canister};
 
@@ -606,7 +608,10 @@ mod simple_calls {
 
             assert!(matches!(
                 result,
-                Err(AgentError::ReplicaError { reject_code: 3, .. })
+                Err(AgentError::ReplicaError {
+                    reject_code: RejectCode::DestinationInvalid,
+                    ..
+                })
             ));
             Ok(())
         })
@@ -625,7 +630,10 @@ mod simple_calls {
 
             assert!(matches!(
                 result,
-                Err(AgentError::ReplicaError { reject_code: 3, .. })
+                Err(AgentError::ReplicaError {
+                    reject_code: RejectCode::DestinationInvalid,
+                    ..
+                })
             ));
             Ok(())
         })
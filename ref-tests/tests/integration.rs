@@ -3,6 +3,7 @@
 //! Contrary to ic-ref.rs, these tests are not meant to match any other tests. They're
 //! integration tests with a running IC-Ref.
 use ic_agent::agent::agent_error::HttpErrorPayload;
+use ic_agent::agent::RejectCode;
 use ic_agent::export::Principal;
 use ic_agent::AgentError;
 use ic_utils::call::AsyncCall;
@@ -102,7 +103,7 @@ fn canister_reject_call() {
         assert_eq!(
             result,
             Err(AgentError::ReplicaError {
-                reject_code: 3,
+                reject_code: RejectCode::DestinationInvalid,
                 reject_message: "method does not exist: wallet_send".to_string()
             })
         );